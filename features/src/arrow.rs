@@ -14,6 +14,7 @@ pub fn rows_to_record_batch(rows: &[Row]) -> Result<RecordBatch, ArrowError> {
 
     let mut game_id_builder     = UInt16Builder::with_capacity(n_rows);
     let mut move_index_builder  = UInt16Builder::with_capacity(n_rows);
+    let mut mirrored_builder    = BooleanBuilder::with_capacity(n_rows);
     let mut state_builder       = UInt16Builder::with_capacity(n_rows);
     let mut ground_truth_builder = Float32Builder::with_capacity(n_rows);
 
@@ -30,8 +31,9 @@ pub fn rows_to_record_batch(rows: &[Row]) -> Result<RecordBatch, ArrowError> {
     for row in rows {
         game_id_builder.append_value(row.game_id);
         move_index_builder.append_value(row.move_index);
+        mirrored_builder.append_value(row.mirrored);
         state_builder.append_value(row.state as u16);
-        ground_truth_builder.append_value(row.ground_truth as f32);
+        ground_truth_builder.append_option(row.ground_truth);
         
         let values0 = row.features.0.values();
         let values1 = row.features.1.values();
@@ -44,6 +46,7 @@ pub fn rows_to_record_batch(rows: &[Row]) -> Result<RecordBatch, ArrowError> {
 
     let game_id     = Arc::new(game_id_builder.finish())     as ArrayRef;
     let move_index  = Arc::new(move_index_builder.finish())  as ArrayRef;
+    let mirrored    = Arc::new(mirrored_builder.finish())    as ArrayRef;
     let state  = Arc::new(state_builder.finish())  as ArrayRef;
     let ground_truth = Arc::new(ground_truth_builder.finish()) as ArrayRef;
 
@@ -59,8 +62,9 @@ pub fn rows_to_record_batch(rows: &[Row]) -> Result<RecordBatch, ArrowError> {
     let mut fields = vec![
         Field::new("game_id", DataType::UInt16, false),
         Field::new("move_index", DataType::UInt16, false),
+        Field::new("mirrored", DataType::Boolean, false),
         Field::new("state", DataType::UInt16, false),
-        Field::new("ground_truth", DataType::Float32, false),
+        Field::new("ground_truth", DataType::Float32, true),
     ];
 
     for i in 0..n_feat {
@@ -73,7 +77,7 @@ pub fn rows_to_record_batch(rows: &[Row]) -> Result<RecordBatch, ArrowError> {
 
     let schema = Arc::new(Schema::new(fields));
 
-    let mut columns = vec![game_id, move_index, state, ground_truth];
+    let mut columns = vec![game_id, move_index, mirrored, state, ground_truth];
     columns.extend(feature_arrays);
 
     RecordBatch::try_new(schema, columns)
@@ -0,0 +1,157 @@
+use std::fmt;
+
+use tetris::board::Board;
+
+/*
+    Board BLOBs in the `Data` table: one byte per cell, index x + y * 10,
+    y counting up from the bottom, nonzero for filled. The number of rows is
+    the length / 10, 20 normally but more when garbage pushed the stack up.
+*/
+
+pub const width: usize = 10;
+pub const default_height: usize = 20;
+// a Board column is a u64
+pub const max_height: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoardError {
+    // not a whole number of rows, or no rows at all
+    Length(usize),
+    // more rows than a Board can hold
+    TooTall(usize),
+    // encoding into fewer rows than the stack needs
+    Overflow { height: usize, needed: usize },
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoardError::Length(len) => write!(f, "{} bytes is not a whole number of {} wide rows", len, width),
+            BoardError::TooTall(rows) => write!(f, "{} rows, at most {} fit in a board", rows, max_height),
+            BoardError::Overflow { height, needed } => write!(f, "stack needs {} rows, only {} to encode into", needed, height),
+        }
+    }
+}
+
+impl std::error::Error for BoardError {}
+
+// Decodes a BLOB with exactly `height` rows
+pub fn decode_with_height(bytes: &[u8], height: usize) -> Result<Board, BoardError> {
+    if height > max_height {
+        return Err(BoardError::TooTall(height));
+    }
+    if height == 0 || bytes.len() != height * width {
+        return Err(BoardError::Length(bytes.len()));
+    }
+
+    let mut board = Board::new();
+
+    for (i, &cell) in bytes.iter().enumerate() {
+        if cell != 0 {
+            board.cols[i % width] |= 1u64 << (i / width);
+        }
+    }
+
+    Ok(board)
+}
+
+// Decodes a BLOB of any height, taken from its length
+pub fn decode(bytes: &[u8]) -> Result<Board, BoardError> {
+    if bytes.len() % width != 0 {
+        return Err(BoardError::Length(bytes.len()));
+    }
+
+    decode_with_height(bytes, bytes.len() / width)
+}
+
+// rows needed to store `board`, never less than the usual 20
+pub fn encoded_height(board: &Board) -> usize {
+    let stack = board.heights().iter().copied().max().unwrap_or(0) as usize;
+    stack.max(default_height)
+}
+
+// Encodes the bottom `height` rows, failing if anything sits above them
+pub fn encode_with_height(board: &Board, height: usize) -> Result<Vec<u8>, BoardError> {
+    if height > max_height {
+        return Err(BoardError::TooTall(height));
+    }
+
+    let needed = board.heights().iter().copied().max().unwrap_or(0) as usize;
+    if needed > height {
+        return Err(BoardError::Overflow { height, needed });
+    }
+
+    let mut bytes = vec![0u8; height * width];
+
+    for (i, cell) in bytes.iter_mut().enumerate() {
+        *cell = ((board.cols[i % width] >> (i / width)) & 1) as u8;
+    }
+
+    Ok(bytes)
+}
+
+// Encodes with as many rows as the stack needs, so decode gives the same board back
+pub fn encode(board: &Board) -> Vec<u8> {
+    encode_with_height(board, encoded_height(board)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(cells: &[(i8, i8)]) -> Board {
+        let mut board = Board::new();
+        for &(x, y) in cells {
+            board.set(x, y);
+        }
+        board
+    }
+
+    #[test]
+    fn round_trips() {
+        for b in [
+            Board::new(),
+            board(&[(0, 0), (9, 0), (4, 7)]),
+            // every column, including the last one
+            board(&(0..10).map(|x| (x, x)).collect::<Vec<_>>()),
+            // taller than the usual 20 rows
+            board(&[(9, 30), (0, 63)]),
+        ] {
+            let bytes = encode(&b);
+            assert_eq!(bytes.len() % width, 0);
+            assert_eq!(decode(&bytes), Ok(b));
+        }
+    }
+
+    #[test]
+    fn column_nine_is_decoded() {
+        let mut bytes = vec![0u8; default_height * width];
+        bytes[9] = 1;
+
+        assert_eq!(decode(&bytes).unwrap().cols[9], 1);
+    }
+
+    #[test]
+    fn layout_is_row_major_from_the_bottom() {
+        let bytes = encode_with_height(&board(&[(2, 1)]), default_height).unwrap();
+
+        assert_eq!(bytes.len(), 200);
+        assert_eq!(bytes.iter().position(|&c| c != 0), Some(2 + width));
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        assert_eq!(decode(&[]), Err(BoardError::Length(0)));
+        assert_eq!(decode(&[0; 199]), Err(BoardError::Length(199)));
+        assert_eq!(decode(&[0; 650]), Err(BoardError::TooTall(65)));
+        assert_eq!(decode_with_height(&[0; 200], 21), Err(BoardError::Length(200)));
+    }
+
+    #[test]
+    fn encode_refuses_to_cut_the_stack() {
+        let tall = board(&[(5, 24)]);
+
+        assert_eq!(encoded_height(&tall), 25);
+        assert_eq!(encode_with_height(&tall, 20), Err(BoardError::Overflow { height: 20, needed: 25 }));
+    }
+}
@@ -8,6 +8,10 @@ pub struct Features {
     pub height_differences:[i16;9],
     pub first_hole_depths:[i16;10],
     pub garbage_holes:[i16;20],
+    pub garbage_height:i16,
+    pub garbage_hole_columns:i16,
+    pub garbage_hole_changes:i16,
+    pub garbage_pattern:i16,
     pub piece_distance:[i16;7],
     pub piece_counts:[i16;7],
     pub hold_or_current_onehot:[i16;7],
@@ -27,6 +31,7 @@ pub struct Features {
     pub meter: i16,
     pub combo: i16,
     pub b2b: i16,
+    pub max_combo: i16,
 
     pub sunbeam_max_height:u32,
     pub sunbeam_bumpiness:i16,
@@ -44,11 +49,53 @@ pub struct Features {
 pub struct Row {
     pub game_id:     u16,
     pub move_index:  u16,
+    // a left-right mirror image of the position at (game_id, move_index)
+    pub mirrored:    bool,
     pub state:       game::State,
-    pub ground_truth: f32,
+    // None for rows the target scheme doesn't label, NULL in training_data
+    pub ground_truth: Option<f32>,
     pub features:    (Features, Features),
 }
 
+// Width of the model input: Features::values() for p1 then p2. This is the training_data
+// table once train/data.py drops game_id, move_index, mirrored, state and ground_truth.
+pub const input_width: usize = Features::count * 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputWidthError {
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl std::fmt::Display for InputWidthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "model takes {} inputs but the feature extractor produces {}", self.found, self.expected)
+    }
+}
+
+impl std::error::Error for InputWidthError {}
+
+// call when loading a model, so a model trained on a different feature set fails loudly
+pub fn check_input_width(found: usize) -> Result<(), InputWidthError> {
+    if found != input_width {
+        return Err(InputWidthError { expected: input_width, found });
+    }
+    Ok(())
+}
+
+// the model input for one position, in the same order as the training columns
+pub fn feature_vector(datum: &game::Datum) -> Vec<f32> {
+    let p1 = extract_features(&datum.p1);
+    let p2 = extract_features(&datum.p2);
+
+    let mut ret = Vec::with_capacity(input_width);
+    ret.extend(p1.values().into_iter().map(|v| v as f32));
+    ret.extend(p2.values().into_iter().map(|v| v as f32));
+
+    debug_assert_eq!(ret.len(), input_width);
+    ret
+}
+
 pub fn extract_features(game: &game::GameState) -> Features {
     let sf = static_features::get_static_features(&game);
     let hf = hachi_features::get_hachi_features(&game);
@@ -58,6 +105,10 @@ pub fn extract_features(game: &game::GameState) -> Features {
         height_differences: hf.height_differences,
         first_hole_depths: hf.first_hole_depths,
         garbage_holes: hf.garbage_holes,
+        garbage_height: hf.garbage_height,
+        garbage_hole_columns: hf.garbage_hole_columns,
+        garbage_hole_changes: hf.garbage_hole_changes,
+        garbage_pattern: hf.garbage_pattern,
         piece_distance: hf.piece_distance,
         piece_counts: hf.piece_counts,
         hold_or_current_onehot: hf.hold_or_current_onehot,
@@ -80,6 +131,7 @@ pub fn extract_features(game: &game::GameState) -> Features {
         meter: hf.meter,
         combo: hf.combo,
         b2b: hf.b2b,
+        max_combo: hf.max_combo,
 
         sunbeam_max_height: sf.sunbeam_max_height,
         sunbeam_bumpiness: sf.sunbeam_bumpiness,
@@ -98,304 +150,229 @@ pub fn extract_features(game: &game::GameState) -> Features {
 const use_3x3s:bool = true;
 const use_positional_3x3s:bool = true;
 
+// bump whenever a group is added, removed, reordered or computed differently
+pub const feature_set_version: u32 = 7;
+
+pub enum Indices {
+    Scalar,
+    Range(usize),
+    Whitelist(&'static [usize]),
+}
+
+// One named block of columns. `value` gets the index from `indices`,
+// which is the position for ranges and the pattern id for whitelists.
+pub struct FeatureGroup {
+    pub name: &'static str,
+    pub indices: Indices,
+    pub value: fn(&Features, usize) -> i16,
+}
+
+impl FeatureGroup {
+    pub const fn len(&self) -> usize {
+        match self.indices {
+            Indices::Scalar => 1,
+            Indices::Range(n) => n,
+            Indices::Whitelist(w) => w.len(),
+        }
+    }
+
+    pub fn index(&self, k: usize) -> usize {
+        match self.indices {
+            Indices::Scalar => 0,
+            Indices::Range(_) => k,
+            Indices::Whitelist(w) => w[k],
+        }
+    }
+
+    pub fn column(&self, prefix: &str, k: usize) -> String {
+        match self.indices {
+            Indices::Scalar => format!("{}_{}", prefix, self.name),
+            _ => format!("{}_{}{}", prefix, self.name, self.index(k)),
+        }
+    }
+}
+
+const fn whitelist_if(enabled: bool, list: &'static [usize]) -> Indices {
+    Indices::Whitelist(if enabled { list } else { &[] })
+}
+
+// The single source of truth for column names and value order.
+pub const registry: &[FeatureGroup] = &[
+    FeatureGroup { name: "heights", indices: Indices::Range(10), value: |f, i| f.heights[i] as i16 },
+    FeatureGroup { name: "height_differences", indices: Indices::Range(9), value: |f, i| f.height_differences[i] },
+    FeatureGroup { name: "first_hole_depths", indices: Indices::Range(10), value: |f, i| f.first_hole_depths[i] },
+    FeatureGroup { name: "garbage_holes", indices: Indices::Range(20), value: |f, i| f.garbage_holes[i] },
+    FeatureGroup { name: "garbage_height", indices: Indices::Scalar, value: |f, _| f.garbage_height },
+    FeatureGroup { name: "garbage_hole_columns", indices: Indices::Scalar, value: |f, _| f.garbage_hole_columns },
+    FeatureGroup { name: "garbage_hole_changes", indices: Indices::Scalar, value: |f, _| f.garbage_hole_changes },
+    FeatureGroup { name: "garbage_pattern", indices: Indices::Scalar, value: |f, _| f.garbage_pattern },
+    FeatureGroup { name: "piece_distance", indices: Indices::Range(7), value: |f, i| f.piece_distance[i] },
+    FeatureGroup { name: "piece_counts", indices: Indices::Range(7), value: |f, i| f.piece_counts[i] },
+    FeatureGroup { name: "hold_or_current_onehot", indices: Indices::Range(7), value: |f, i| f.hold_or_current_onehot[i] },
+    FeatureGroup { name: "next_onehot", indices: Indices::Range(7), value: |f, i| f.next_onehot[i] },
+
+    FeatureGroup { name: "all_3x3s", indices: whitelist_if(use_3x3s, &whitelist::top_100_3x3s), value: |f, i| f.all_3x3s[i] },
+    FeatureGroup { name: "all_3x3s_with_x", indices: whitelist_if(use_positional_3x3s, &whitelist::top_100_3x3s_with_x), value: |f, i| f.all_3x3s_with_x[i] },
+    FeatureGroup { name: "all_3x3s_with_y", indices: whitelist_if(use_positional_3x3s, &whitelist::top_100_3x3s_with_y), value: |f, i| f.all_3x3s_with_y[i] },
+
+    FeatureGroup { name: "all_2x2s", indices: Indices::Range(16), value: |f, i| f.all_2x2s[i] },
+    FeatureGroup { name: "all_2x2s_with_x", indices: Indices::Range(16), value: |f, i| f.all_2x2s_with_x[i] },
+    FeatureGroup { name: "all_2x2s_with_y", indices: Indices::Range(16), value: |f, i| f.all_2x2s_with_y[i] },
+
+    FeatureGroup { name: "all_2x3s", indices: Indices::Range(64), value: |f, i| f.all_2x3s[i] },
+    FeatureGroup { name: "all_2x3s_with_x", indices: Indices::Range(64), value: |f, i| f.all_2x3s_with_x[i] },
+    FeatureGroup { name: "all_2x3s_with_y", indices: Indices::Range(64), value: |f, i| f.all_2x3s_with_y[i] },
+
+    FeatureGroup { name: "all_3x2s", indices: Indices::Range(64), value: |f, i| f.all_3x2s[i] },
+    FeatureGroup { name: "all_3x2s_with_x", indices: Indices::Range(64), value: |f, i| f.all_3x2s_with_x[i] },
+    FeatureGroup { name: "all_3x2s_with_y", indices: Indices::Range(64), value: |f, i| f.all_3x2s_with_y[i] },
+
+    // hachi scalars
+    FeatureGroup { name: "attack_received", indices: Indices::Scalar, value: |f, _| f.meter },
+    FeatureGroup { name: "combo", indices: Indices::Scalar, value: |f, _| f.combo },
+    FeatureGroup { name: "b2b", indices: Indices::Scalar, value: |f, _| f.b2b },
+    FeatureGroup { name: "max_combo", indices: Indices::Scalar, value: |f, _| f.max_combo },
+
+    // sunbeam
+    FeatureGroup { name: "sunbeam_max_height", indices: Indices::Scalar, value: |f, _| f.sunbeam_max_height as i16 },
+    FeatureGroup { name: "sunbeam_bumpiness", indices: Indices::Scalar, value: |f, _| f.sunbeam_bumpiness },
+    FeatureGroup { name: "sunbeam_well_x", indices: Indices::Scalar, value: |f, _| f.sunbeam_well_x as i16 },
+    FeatureGroup { name: "sunbeam_well_depth", indices: Indices::Scalar, value: |f, _| f.sunbeam_well_depth },
+    FeatureGroup { name: "sunbeam_max_donated_height", indices: Indices::Scalar, value: |f, _| f.sunbeam_max_donated_height as i16 },
+    FeatureGroup { name: "sunbeam_n_donations", indices: Indices::Scalar, value: |f, _| f.sunbeam_n_donations },
+    FeatureGroup { name: "sunbeam_t_clears", indices: Indices::Range(4), value: |f, i| f.sunbeam_t_clears[i] },
+
+    // cold clear
+    FeatureGroup { name: "cc_holes", indices: Indices::Scalar, value: |f, _| f.cc_holes },
+    FeatureGroup { name: "cc_coveredness", indices: Indices::Scalar, value: |f, _| f.cc_coveredness },
+    FeatureGroup { name: "cc_row_transitions", indices: Indices::Scalar, value: |f, _| f.cc_row_transitions },
+];
+
+const fn registry_count() -> usize {
+    let mut n = 0;
+    let mut i = 0;
+    while i < registry.len() {
+        n += registry[i].len();
+        i += 1;
+    }
+    n
+}
+
 impl Features {
     pub fn sql_columns(prefix: &str) -> String {
         Self::sql_columns_with_options(prefix, false)
     }
-    
+
     pub fn sql_columns_with_types(prefix: &str) -> String {
         Self::sql_columns_with_options(prefix, true)
     }
-    
-    fn sql_columns_with_options(prefix: &str, include_types: bool) -> String {
-        let mut columns = Vec::new();
-        
-        let type_suffix = if include_types { " SMALLINT NOT NULL" } else { "" };
-        
-        // heights array
-        for i in 0..10 {
-            columns.push(format!("{}_{}{}{}", prefix, "heights", i, type_suffix));
-        }
-        
-        // height_differences array
-        for i in 0..9 {
-            columns.push(format!("{}_{}{}{}", prefix, "height_differences", i, type_suffix));
-        }
-        
-        // first_hole_depths array
-        for i in 0..10 {
-            columns.push(format!("{}_{}{}{}", prefix, "first_hole_depths", i, type_suffix));
-        }
 
-        for i in 0..20 {
-            columns.push(format!("{}_{}{}{}", prefix, "garbage_holes", i, type_suffix));
-        }
-        
-        // piece_distance array
-        for i in 0..7 {
-            columns.push(format!("{}_{}{}{}", prefix, "piece_distance", i, type_suffix));
-        }
-        
-        // piece_counts array
-        for i in 0..7 {
-            columns.push(format!("{}_{}{}{}", prefix, "piece_counts", i, type_suffix));
-        }
-        
-        // hold_or_current_onehot array
-        for i in 0..7 {
-            columns.push(format!("{}_{}{}{}", prefix, "hold_or_current_onehot", i, type_suffix));
-        }
-        
-        // next_onehot array
-        for i in 0..7 {
-            columns.push(format!("{}_{}{}{}", prefix, "next_onehot", i, type_suffix));
-        }
-        
-        if use_3x3s {
-            // all_3x3s array
-            for i in whitelist::top_100_3x3s.iter() {
-                columns.push(format!("{}_{}{}{}", prefix, "all_3x3s", i, type_suffix));
-            }
-        }
-        
-        if use_positional_3x3s {
-            // all_3x3s_with_x array
-            for i in whitelist::top_100_3x3s_with_x.iter() {
-                columns.push(format!("{}_{}{}{}", prefix, "all_3x3s_with_x", i, type_suffix));
-            }
+    // column names in value order, without types
+    pub fn column_names(prefix: &str) -> Vec<String> {
+        let mut columns = Vec::with_capacity(Features::count);
 
-            // all_3x3s_with_y array
-            for i in whitelist::top_100_3x3s_with_y.iter() {
-                columns.push(format!("{}_{}{}{}", prefix, "all_3x3s_with_y", i, type_suffix));
+        for group in registry {
+            for k in 0..group.len() {
+                columns.push(group.column(prefix, k));
             }
         }
-        
-        // all_2x2s array
-        for i in 0..16 {
-            columns.push(format!("{}_{}{}{}", prefix, "all_2x2s", i, type_suffix));
-        }
-        // all_2x2s_with_x array
-        for i in 0..16 {
-            columns.push(format!("{}_{}{}{}", prefix, "all_2x2s_with_x", i, type_suffix));
-        }
-        // all_2x2s_with_y array
-        for i in 0..16 {
-            columns.push(format!("{}_{}{}{}", prefix, "all_2x2s_with_y", i, type_suffix));
-        }
 
-        // all_2x3s array
-        for i in 0..64 {
-            columns.push(format!("{}_{}{}{}", prefix, "all_2x3s", i, type_suffix));
-        }
-        // all_2x3s_with_x array
-        for i in 0..64 {
-            columns.push(format!("{}_{}{}{}", prefix, "all_2x3s_with_x", i, type_suffix));
-        }
-        // all_2x3s_with_y array
-        for i in 0..64 {
-            columns.push(format!("{}_{}{}{}", prefix, "all_2x3s_with_y", i, type_suffix));
-        }
-        
-        // all_3x2s array
-        for i in 0..64 {
-            columns.push(format!("{}_{}{}{}", prefix, "all_3x2s", i, type_suffix));
-        }
-        // all_3x2s_with_x array
-        for i in 0..64 {
-            columns.push(format!("{}_{}{}{}", prefix, "all_3x2s_with_x", i, type_suffix));
-        }
-        // all_3x2s_with_y array
-        for i in 0..64 {
-            columns.push(format!("{}_{}{}{}", prefix, "all_3x2s_with_y", i, type_suffix));
-        }
-
-        // hachi scalar
-        columns.push(format!("{}_{}{}", prefix, "attack_received", type_suffix));
-        columns.push(format!("{}_{}{}", prefix, "combo", type_suffix));
-        columns.push(format!("{}_{}{}", prefix, "b2b", type_suffix));
-
-        // sunbeam scalar fields
-        columns.push(format!("{}_{}{}", prefix, "sunbeam_max_height", type_suffix));
-        columns.push(format!("{}_{}{}", prefix, "sunbeam_bumpiness", type_suffix));
-        columns.push(format!("{}_{}{}", prefix, "sunbeam_well_x", type_suffix));
-        columns.push(format!("{}_{}{}", prefix, "sunbeam_well_depth", type_suffix));
-        columns.push(format!("{}_{}{}", prefix, "sunbeam_max_donated_height", type_suffix));
-        columns.push(format!("{}_{}{}", prefix, "sunbeam_n_donations", type_suffix));
-        
-        // sunbeam_t_clears array
-        for i in 0..4 {
-            columns.push(format!("{}_{}{}{}", prefix, "sunbeam_t_clears", i, type_suffix));
-        }
-        
-        // cc scalar fields
-        columns.push(format!("{}_{}{}", prefix, "cc_holes", type_suffix));
-        columns.push(format!("{}_{}{}", prefix, "cc_coveredness", type_suffix));
-        columns.push(format!("{}_{}{}", prefix, "cc_row_transitions", type_suffix));
-        
-        columns.join(", ")
+        columns
     }
 
-    pub const count:usize =
-        10 + // heights
-        9 + // height differences
-        10 + // first hole depths
-        20 + // garbage holes
-        7 + // piece_distance
-        7 +  // piece counts
-        7 +  // hold or current
-        7 +  // next
-        if use_3x3s {whitelist::top_100_3x3s.len()} else {0} +  // 3x3s
-        if use_positional_3x3s {whitelist::top_100_3x3s_with_x.len()} else {0} + // 3x3s with x
-        if use_positional_3x3s {whitelist::top_100_3x3s_with_y.len()} else {0} + // 3x3s with y
-        16 +  // 2x2s
-        16 + // 2x2s with x
-        16 + // 2x2s with y
-        
-        64 +  // 2x3s
-        64 + // 2x3s with x
-        64 + // 2x3s with y
-        
-        64 +  // 3x2s
-        64 + // 3x2s with x
-        64 + // 3x2s with y
+    fn sql_columns_with_options(prefix: &str, include_types: bool) -> String {
+        let type_suffix = if include_types { " SMALLINT NOT NULL" } else { "" };
 
-        3 + // hachi scalars
-        6 +  // sunbeam scalars
-        4 +  // sunbeam_t_clears
-        3   // cc scalars
-    ;
+        Self::column_names(prefix)
+            .into_iter()
+            .map(|column| format!("{}{}", column, type_suffix))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub const count:usize = registry_count();
 
     pub fn sql_placeholders() -> String {
         
         vec!["?"; Features::count].join(", ")
     }
-}
 
-impl Features {
     pub fn values(&self) -> Vec<i16> {
-        let mut vals = Vec::new();
-        
-        // heights array
-        for &h in &self.heights {
-            vals.push(h as i16);
-        }
-        
-        // height_differences array
-        for &hd in &self.height_differences {
-            vals.push(hd as i16);
-        }
-        
-        // first_hole_depths array
-        for &fhd in &self.first_hole_depths {
-            vals.push(fhd as i16);
-        }
-        
-        // garbage_holes array
-        for &gh in &self.garbage_holes {
-            vals.push(gh as i16);
-        }
-
-        // piece_distance array
-        for &pd in &self.piece_distance {
-            vals.push(pd as i16);
-        }
-        
-        // piece_counts array
-        for &pc in &self.piece_counts {
-            vals.push(pc as i16);
-        }
-        
-        // hold_or_current_onehot array
-        for &hoc in &self.hold_or_current_onehot {
-            vals.push(hoc as i16);
-        }
-        
-        // next_onehot array
-        for &no in &self.next_onehot {
-            vals.push(no as i16);
-        }
+        let mut vals = Vec::with_capacity(Features::count);
 
-        if use_3x3s {
-            // 3x3s
-            for i in whitelist::top_100_3x3s.iter() {
-                vals.push(self.all_3x3s[*i] as i16);
+        for group in registry {
+            for k in 0..group.len() {
+                vals.push((group.value)(self, group.index(k)));
             }
         }
 
-        if use_positional_3x3s {
+        vals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
 
-            // 3x3s with x
-            for i in whitelist::top_100_3x3s_with_x.iter() {
-                vals.push(self.all_3x3s_with_x[*i] as i16);
-            }
-            
-            // 3x3s with y
-            for i in whitelist::top_100_3x3s_with_y.iter() {
-                vals.push(self.all_3x3s_with_y[*i] as i16);
-            }
-        }
-        
-        // 2x2s
-        for i in 0..16 {
-            vals.push(self.all_2x2s[i] as i16);
-        }
-        // 2x2s with x
-        for i in 0..16 {
-            vals.push(self.all_2x2s_with_x[i] as i16);
-        }
-        // 2x2s with y
-        for i in 0..16 {
-            vals.push(self.all_2x2s_with_y[i] as i16);
-        }
-        
-        // 2x3s
-        for i in 0..64 {
-            vals.push(self.all_2x3s[i] as i16);
-        }
-        // 2x3s with x
-        for i in 0..64 {
-            vals.push(self.all_2x3s_with_x[i] as i16);
-        }
-        // 2x3s with y
-        for i in 0..64 {
-            vals.push(self.all_2x3s_with_y[i] as i16);
-        }
-        
-        // 3x2s
-        for i in 0..64 {
-            vals.push(self.all_3x2s[i] as i16);
-        }
-        // 3x2s with x
-        for i in 0..64 {
-            vals.push(self.all_3x2s_with_x[i] as i16);
-        }
-        // 3x2s with y
-        for i in 0..64 {
-            vals.push(self.all_3x2s_with_y[i] as i16);
-        }
+    use tetris::piece::Piece;
 
-        vals.push(self.meter as i16);
-        vals.push(self.combo as i16);
-        vals.push(self.b2b as i16);
-        
-        // sunbeam scalar fields
-        vals.push(self.sunbeam_max_height as i16);
-        vals.push(self.sunbeam_bumpiness as i16);
-        vals.push(self.sunbeam_well_x as i16);
-        vals.push(self.sunbeam_well_depth as i16);
-        vals.push(self.sunbeam_max_donated_height as i16);
-        vals.push(self.sunbeam_n_donations as i16);
-        
-        // sunbeam_t_clears array
-        for &tc in &self.sunbeam_t_clears {
-            vals.push(tc as i16);
+    use super::*;
+    use crate::game::{Datum, GameState, State};
+    use crate::manifest::input_columns;
+
+    fn datum() -> Datum {
+        let mut p1 = GameState::new(Piece::T, [Piece::I, Piece::O, Piece::S, Piece::Z, Piece::L]);
+        p1.board.set(0, 0);
+        p1.board.set(9, 2);
+        p1.damage_received = 3;
+
+        Datum {
+            p1,
+            p2: GameState::new(Piece::J, [Piece::T; 5]),
+            state: State::PLAYING,
+            game_id: 0,
+            move_index: 0,
         }
-        
-        // cc scalar fields
-        vals.push(self.cc_holes as i16);
-        vals.push(self.cc_coveredness as i16);
-        vals.push(self.cc_row_transitions as i16);
-        
-        vals
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn names_and_values_line_up() {
+        let names = Features::column_names("p1");
+
+        assert_eq!(names.len(), Features::count);
+        assert_eq!(extract_features(&datum().p1).values().len(), Features::count);
+        assert_eq!(names.iter().collect::<HashSet<_>>().len(), names.len());
+    }
+
+    #[test]
+    fn manifest_columns_are_p1_then_p2() {
+        let columns = input_columns();
+
+        assert_eq!(columns.len(), input_width);
+        assert_eq!(columns[..Features::count], Features::column_names("p1")[..]);
+        assert_eq!(columns[Features::count..], Features::column_names("p2")[..]);
+        assert_eq!(Features::sql_columns("p1").split(", ").collect::<Vec<_>>(), Features::column_names("p1"));
+    }
+
+    #[test]
+    fn feature_vector_follows_the_columns() {
+        let datum = datum();
+        let vector = feature_vector(&datum);
+        let columns = input_columns();
+
+        assert_eq!(vector.len(), input_width);
+
+        let at = |name: &str| vector[columns.iter().position(|c| c == name).unwrap()];
+        assert_eq!(at("p1_heights0"), 1.0);
+        assert_eq!(at("p1_heights9"), 3.0);
+        assert_eq!(at("p1_attack_received"), 3.0);
+        assert_eq!(at("p2_heights0"), 0.0);
+        assert_eq!(at("p2_attack_received"), 0.0);
+    }
+
+    #[test]
+    fn input_width_is_checked() {
+        assert!(check_input_width(input_width).is_ok());
+        assert!(check_input_width(input_width - 10).is_err());
+    }
+}
@@ -23,6 +23,30 @@ pub struct GameState {
     pub hold:Option<Piece>
 }
 
+impl GameState {
+    // empty board, nothing held, no garbage
+    pub fn new(current_piece: Piece, queue: [Piece; 5]) -> Self {
+        GameState {
+            board: Board::new(),
+            current_piece,
+            placement: Move {
+                move_type: None,
+                rotation: Rotation::North,
+                x: 0,
+                y: 0,
+            },
+            meter: 0,
+            combo: 0,
+            attack: 0,
+            b2b: 0,
+            damage_received: 0,
+            spun: false,
+            queue,
+            hold: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
 pub enum State {
     PLAYING, // 0
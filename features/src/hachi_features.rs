@@ -1,5 +1,10 @@
+use std::collections::HashMap;
+
 use tetris::board::Board;
+use tetris::movegen::movegen;
+use tetris::piece::Piece;
 use crate::game::{GameState};
+use crate::placement::{hold_piece, play};
 
 fn get_heights(board: &Board) -> [u32; 10] {
     let mut heights = board.heights();
@@ -33,29 +38,67 @@ fn get_first_hole_depths(board: &Board) -> [i16; 10] {
     depths
 }
 
-// locations of all garbage holes
+// one bit per column for row `y`
+fn row_bits(board: &Board, y: usize) -> u16 {
+    board.cols.iter().enumerate().fold(0, |row, (x, col)| {
+        row | ((((col >> y) & 1) as u16) << x)
+    })
+}
+
+// column of the single hole in a garbage row, None if the row isn't exactly 9 wide
+fn garbage_hole(row: u16) -> Option<u32> {
+    if row.count_ones() == 9 {
+        Some((!row & 0x3ff).trailing_zeros())
+    } else {
+        None
+    }
+}
+
+// hole column of each of the bottom 20 rows (-1 for rows that aren't garbage)
 fn get_garbage_hole_sequence(board: &Board) -> [i16; 20] {
     let mut locations = [-1; 20];
+
     for y in 0..20 {
-        let xs: Vec<i16> = board.cols.iter().map(|col| {
-            ((col >> y) & 1) as i16
-        }).collect();
-        
-        let mut sum = 0;
-        for x in 1..10 {
-            sum += xs[x];
-            if xs[x] == 0 {
-                locations[y] = x as i16;
-            }
-        }
-        if sum != 9 {
-            locations[y] = -1;
+        if let Some(hole) = garbage_hole(row_bits(board, y)) {
+            locations[y] = hole as i16;
         }
     }
 
     locations
 }
 
+// The unbroken run of garbage rows from the floor up:
+// (rows in it, distinct hole columns, times the hole moves between neighbouring rows,
+// 0 for no garbage / 1 for clean, one hole column / 2 for cheese)
+fn get_garbage_stack(board: &Board) -> (i16, i16, i16, i16) {
+    let mut height = 0;
+    let mut columns = 0u16;
+    let mut changes = 0;
+    let mut last = None;
+
+    for y in 0..64 {
+        let Some(hole) = garbage_hole(row_bits(board, y)) else {
+            break;
+        };
+
+        if last.is_some_and(|last| last != hole) {
+            changes += 1;
+        }
+
+        last = Some(hole);
+        columns |= 1 << hole;
+        height += 1;
+    }
+
+    let pattern = match (height, changes) {
+        (0, _) => 0,
+        (_, 0) => 1,
+        _ => 2,
+    };
+
+    (height, columns.count_ones() as i16, changes, pattern)
+}
+
 fn get_distance_to_next_piece(gamestate: &GameState) -> [i16; 7] {
     let queue = gamestate.queue;
     let mut distances = [5;7];
@@ -78,8 +121,84 @@ fn get_count_of_pieces(gamestate: &GameState) -> [i16; 7] {let queue = gamestate
 }
 
 // more complex, requires lookahead calculation
+// longest run of consecutive line clears reachable with the current piece, queue and hold
 fn get_maximum_combo(gamestate: &GameState) -> i16 {
-    0
+    let mut search = ComboSearch { seen: HashMap::new(), nodes: 0 };
+    max_combo_from(gamestate, 1 + gamestate.queue.len(), &mut search, true).0 as i16
+}
+
+// Placements tried under each first placement before settling for the longest combo found so
+// far. This runs on every extracted row and search leaf, so stacks with many clears can't be
+// allowed to blow up. Every first placement gets the same budget, so none is starved by the
+// ones movegen happened to list before it.
+const max_combo_nodes: usize = 256;
+
+struct ComboSearch {
+    // Exact results by board, current piece, hold and pieces left. Those decide the rest of
+    // the queue, so placements that end up the same way are only searched once. Results cut
+    // short by the budget are not kept, so a position scores the same wherever it is met.
+    seen: HashMap<(Board, Piece, Option<Piece>, usize), usize>,
+    // placements tried under the current first placement
+    nodes: usize,
+}
+
+// `known` is how many of current + queue are real pieces, past that the queue is filler.
+// Returns the longest combo found and whether the search was exhaustive.
+fn max_combo_from(state: &GameState, known: usize, search: &mut ComboSearch, root: bool) -> (usize, bool) {
+    if known == 0 {
+        return (0, true);
+    }
+
+    let key = (state.board, state.current_piece, state.hold, known);
+    if let Some(&best) = search.seen.get(&key) {
+        return (best, true);
+    }
+
+    let filler = state.queue[4];
+    let held = hold_piece(state);
+    let hold_cost = if state.hold.is_some() { 1 } else { 2 };
+
+    let mut pieces = vec![(state.current_piece, 1)];
+    if held != state.current_piece && known >= hold_cost {
+        pieces.push((held, hold_cost));
+    }
+
+    let mut best = 0;
+    let mut exact = true;
+
+    'search: for (piece, cost) in pieces {
+        for mv in movegen(&state.board, piece) {
+            if root {
+                search.nodes = 0;
+            } else if search.nodes >= max_combo_nodes {
+                exact = false;
+                break 'search;
+            }
+            search.nodes += 1;
+
+            let (child, lines) = play(state, &mv, || filler);
+
+            // only clears keep the combo going
+            if lines == 0 {
+                continue;
+            }
+
+            let (length, child_exact) = max_combo_from(&child, known - cost, search, false);
+            best = best.max(1 + length);
+            exact &= child_exact;
+
+            // can't do better than clearing with every known piece
+            if best == known {
+                exact = true;
+                break 'search;
+            }
+        }
+    }
+
+    if exact {
+        search.seen.insert(key, best);
+    }
+    (best, exact)
 }
 
 fn get_height_variance(board: &Board) -> f32 {
@@ -253,6 +372,10 @@ pub struct HachiFeatures {
     pub height_differences:[i16;9],
     pub first_hole_depths:[i16;10],
     pub garbage_holes:[i16;20],
+    pub garbage_height:i16,
+    pub garbage_hole_columns:i16,
+    pub garbage_hole_changes:i16,
+    pub garbage_pattern:i16,
     pub piece_distance:[i16;7],
     pub piece_counts:[i16;7],
     pub hold_or_current_onehot:[i16;7],
@@ -272,6 +395,7 @@ pub struct HachiFeatures {
     pub meter: i16,
     pub combo: i16,
     pub b2b: i16,
+    pub max_combo: i16,
 }
 
 pub fn get_hachi_features(gamestate: &GameState) -> HachiFeatures {
@@ -280,11 +404,16 @@ pub fn get_hachi_features(gamestate: &GameState) -> HachiFeatures {
     let (all_2x2s, all_2x2s_with_x, all_2x2s_with_y) = get_2x2s(&board);
     let (all_2x3s, all_2x3s_with_x, all_2x3s_with_y) = get_2x3s(&board);
     let (all_3x2s, all_3x2s_with_x, all_3x2s_with_y) = get_3x2s(&board);
+    let (garbage_height, garbage_hole_columns, garbage_hole_changes, garbage_pattern) = get_garbage_stack(&board);
     HachiFeatures {
         heights: get_heights(&board),
         height_differences: get_height_differences(&board),
         first_hole_depths: get_first_hole_depths(&board),
         garbage_holes: get_garbage_hole_sequence(&board),
+        garbage_height,
+        garbage_hole_columns,
+        garbage_hole_changes,
+        garbage_pattern,
         piece_distance: get_distance_to_next_piece(&gamestate),
         piece_counts: get_count_of_pieces(&gamestate),
         hold_or_current_onehot: get_hold_or_current_piece(&gamestate),
@@ -304,5 +433,126 @@ pub fn get_hachi_features(gamestate: &GameState) -> HachiFeatures {
         meter: gamestate.damage_received as i16,
         combo: gamestate.combo as i16,
         b2b: gamestate.b2b as i16,
+        max_combo: get_maximum_combo(&gamestate),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // rows 0..rows filled except columns 0 to 3
+    fn gaps(rows: i8) -> Board {
+        let mut board = Board::new();
+        for y in 0..rows {
+            for x in 4..10 {
+                board.set(x, y);
+            }
+        }
+        board
+    }
+
+    fn state(board: Board, current: Piece, queue: [Piece; 5]) -> GameState {
+        let mut state = GameState::new(current, queue);
+        state.board = board;
+        state
+    }
+
+    // a garbage row per hole column, from the floor up
+    fn garbage(holes: &[i8]) -> Board {
+        let mut board = Board::new();
+        for (y, &hole) in holes.iter().enumerate() {
+            for x in (0..10).filter(|&x| x != hole) {
+                board.set(x, y as i8);
+            }
+        }
+        board
+    }
+
+    #[test]
+    fn rows_and_their_holes() {
+        let board = garbage(&[3, 0]);
+
+        assert_eq!(row_bits(&board, 0), 0x3ff & !(1 << 3));
+        assert_eq!(row_bits(&board, 1), 0x3fe);
+        assert_eq!(row_bits(&board, 2), 0);
+
+        assert_eq!(garbage_hole(row_bits(&board, 0)), Some(3));
+        assert_eq!(garbage_hole(row_bits(&board, 1)), Some(0));
+        assert_eq!(garbage_hole(0), None);
+        assert_eq!(garbage_hole(0x3ff), None);
+        assert_eq!(garbage_hole(0x3fc), None);
+    }
+
+    #[test]
+    fn no_garbage_stack() {
+        assert_eq!(get_garbage_stack(&Board::new()), (0, 0, 0, 0));
+        assert_eq!(get_garbage_stack(&gaps(3)), (0, 0, 0, 0));
+        assert_eq!(get_garbage_hole_sequence(&Board::new()), [-1; 20]);
+    }
+
+    #[test]
+    fn clean_garbage_stack() {
+        let board = garbage(&[7, 7, 7, 7]);
+
+        assert_eq!(get_garbage_stack(&board), (4, 1, 0, 1));
+        assert_eq!(get_garbage_hole_sequence(&board)[..5], [7, 7, 7, 7, -1]);
+    }
+
+    #[test]
+    fn cheese_garbage_stack() {
+        let board = garbage(&[2, 2, 5, 0, 5]);
+
+        assert_eq!(get_garbage_stack(&board), (5, 3, 3, 2));
+        assert_eq!(get_garbage_hole_sequence(&board)[..6], [2, 2, 5, 0, 5, -1]);
+    }
+
+    #[test]
+    fn garbage_stack_stops_at_the_first_other_row() {
+        let mut board = garbage(&[4, 4, 1]);
+        // row 1 gets its hole filled, so only row 0 is in the stack from the floor
+        board.set(4, 1);
+
+        assert_eq!(get_garbage_stack(&board), (1, 1, 0, 1));
+        assert_eq!(get_garbage_hole_sequence(&board)[..3], [4, -1, 1]);
+    }
+
+    #[test]
+    fn empty_board_has_no_combo() {
+        assert_eq!(get_maximum_combo(&state(Board::new(), Piece::T, [Piece::I; 5])), 0);
+    }
+
+    #[test]
+    fn one_i_clears_one_row() {
+        assert_eq!(get_maximum_combo(&state(gaps(1), Piece::I, [Piece::O; 5])), 1);
+    }
+
+    #[test]
+    fn i_pieces_clear_row_after_row() {
+        assert_eq!(get_maximum_combo(&state(gaps(2), Piece::I, [Piece::I, Piece::O, Piece::O, Piece::O, Piece::O])), 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn hold_brings_the_i_forward() {
+        assert_eq!(get_maximum_combo(&state(gaps(1), Piece::O, [Piece::I, Piece::O, Piece::O, Piece::O, Piece::O])), 1);
+    }
+
+    #[test]
+    fn search_stays_within_budget() {
+        let mut search = ComboSearch { seen: HashMap::new(), nodes: 0 };
+        max_combo_from(&state(gaps(6), Piece::I, [Piece::I; 5]), 6, &mut search, false);
+
+        assert!(search.nodes <= max_combo_nodes);
+    }
+
+    #[test]
+    fn cut_short_results_are_not_kept() {
+        let mut search = ComboSearch { seen: HashMap::new(), nodes: max_combo_nodes };
+        let position = state(gaps(3), Piece::I, [Piece::I; 5]);
+
+        assert_eq!(max_combo_from(&position, 6, &mut search, false), (0, false));
+        assert!(search.seen.is_empty());
+
+        // a fresh budget for every first placement finds the real value
+        assert_eq!(max_combo_from(&position, 6, &mut search, true).0, 3);
+    }
+}
@@ -3,4 +3,13 @@ pub mod static_features;
 pub mod game;
 pub mod feature_extractor;
 pub mod arrow;
-pub mod whitelist;
\ No newline at end of file
+pub mod whitelist;
+pub mod placement;
+pub mod xgboost;
+pub mod manifest;
+pub mod rng;
+pub mod versus;
+pub mod board_codec;
+pub mod target;
+pub mod relabel;
+pub mod mirror;
@@ -1,6 +1,8 @@
 use tetris::{board::Board, piece::Piece, piece::Rotation};
 use std::{env, fs::exists};
+use std::collections::{BTreeMap, BTreeSet};
 use rayon::prelude::*;
+use std::process::ExitCode;
 use std::time::Instant;
 use std::panic;
 
@@ -12,10 +14,60 @@ use duckdb::{Connection as DuckConnection, Result as DuckResult, Error as DuckEr
 use features::feature_extractor::{Features, Row};
 
 use features::arrow::rows_to_record_batch;
+use features::board_codec::{self, BoardError};
+use features::manifest;
+use features::mirror::mirror_datum;
+use features::relabel;
+use features::target::{Scheme, Target};
+use features::xgboost::TreeModel;
 
 use features::game::{GameState,Move,Datum,State};
 
-fn to_piece(s:&str) -> Result<Piece, ()> {
+#[derive(Debug)]
+enum RowError {
+    Piece { column: String, value: String },
+    Rotation { column: String, value: i32 },
+    Board { column: String, error: BoardError },
+    State { value: String },
+    Column(rusqlite::Error),
+}
+
+impl RowError {
+    // what the report groups rejected rows by
+    fn reason(&self) -> String {
+        match self {
+            RowError::Piece { column, .. } => format!("bad piece in {}", column),
+            RowError::Rotation { column, .. } => format!("bad rotation in {}", column),
+            RowError::Board { column, .. } => format!("bad board in {}", column),
+            RowError::State { .. } => "bad state".to_string(),
+            RowError::Column(rusqlite::Error::InvalidColumnType(_, name, _)) => format!("wrong type in {}", name),
+            RowError::Column(rusqlite::Error::IntegralValueOutOfRange(i, _)) => format!("out of range value in column {}", i),
+            RowError::Column(_) => "unreadable column".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RowError::Piece { column, value } => write!(f, "{} is {:?}, not a piece", column, value),
+            RowError::Rotation { column, value } => write!(f, "{} is {}, not a rotation", column, value),
+            RowError::Board { column, error } => write!(f, "{}: {}", column, error),
+            RowError::State { value } => write!(f, "state is {:?}", value),
+            RowError::Column(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RowError {}
+
+impl From<rusqlite::Error> for RowError {
+    fn from(e: rusqlite::Error) -> Self {
+        RowError::Column(e)
+    }
+}
+
+fn to_piece(s:&str, column:&str) -> Result<Piece, RowError> {
     match s {
         "I" => Ok(Piece::I),
         "J" => Ok(Piece::J),
@@ -24,54 +76,46 @@ fn to_piece(s:&str) -> Result<Piece, ()> {
         "S" => Ok(Piece::S),
         "T" => Ok(Piece::T),
         "Z" => Ok(Piece::Z),
-        _ =>   Err(())
+        _ =>   Err(RowError::Piece { column: column.to_string(), value: s.to_string() })
     }
 }
-fn to_rotation(s:i32) -> Result<Rotation, ()> {
+
+// hold and move type are blank when there is no piece
+fn to_optional_piece(s:&str, column:&str) -> Result<Option<Piece>, RowError> {
+    if s.trim().is_empty() {
+        return Ok(None);
+    }
+    to_piece(s, column).map(Some)
+}
+
+fn to_rotation(s:i32, column:&str) -> Result<Rotation, RowError> {
     return Ok(match s {
         0 => Rotation::North,
         1 => Rotation::East,
         2 => Rotation::South,
         3 => Rotation::West,
-        _ => return Err(())
+        _ => return Err(RowError::Rotation { column: column.to_string(), value: s })
     })
 }
 
-fn to_board(bytes:Vec<u8>) -> Board {
-    let mut ret = Board::new();
-
-    for x in 0..9 {
-        for y in 0..20 {
-            let set = bytes[x + y * 10];
-            if set != 0 {
-                ret.set(x as i8, y as i8);
-            }
-        }
-    }
-    ret
+fn to_board(bytes:Vec<u8>, column:&str) -> Result<Board, RowError> {
+    board_codec::decode(&bytes).map_err(|error| RowError::Board { column: column.to_string(), error })
 }
 
-fn to_state(s:&str) -> Result<State, ()> {
+fn to_state(s:&str) -> Result<State, RowError> {
     Ok(match s {
         "PLAYING" => State::PLAYING,
         "P1_WIN" => State::P1_WIN,
         "P2_WIN" => State::P2_WIN,
         "DRAW" => State::DRAW,
-        _ => return Err(())
-    })
-}
-
-fn to_death_value(s:&State) -> Result<f32, ()> {
-    Ok(match s {
-        State::PLAYING => 0f32,
-        State::P1_WIN => 1f32,
-        State::P2_WIN => -1f32,
-        State::DRAW => 0f32,
-        _ => return Err(())
+        _ => return Err(RowError::State { value: s.to_string() })
     })
 }
 
 /*
+The Data table extract_data reads. hachi's selfplay writes it, the C++ selfplay wrote the
+same columns up to p2_hold. combo and b2b are read too, so they are part of the schema.
+
 const char* sql =
 		"CREATE TABLE IF NOT EXISTS Data ("
 		"game_id INTEGER NOT NULL, "
@@ -88,13 +132,117 @@ const char* sql =
 		"p2_meter INTEGER NOT NULL, p2_attack INTEGER NOT NULL, p2_damage_received INTEGER NOT NULL, "
 		"p2_spun INTEGER NOT NULL, "
 		"p2_queue_0 TEXT NOT NULL, p2_queue_1 TEXT NOT NULL, p2_queue_2 TEXT NOT NULL, p2_queue_3 TEXT NOT NULL, p2_queue_4 TEXT NOT NULL, "
-		"p2_hold TEXT NOT NULL"
+		"p2_hold TEXT NOT NULL, "
+		"p1_combo INTEGER NOT NULL, p1_b2b INTEGER NOT NULL, "
+		"p2_combo INTEGER NOT NULL, p2_b2b INTEGER NOT NULL"
 		");";
 */
 
-fn extract_data(db_path:String) -> Vec<Datum> {
+// moves per chunk, a chunk runs over this to finish its last game
+const default_chunk_rows: usize = 100_000;
+
+// One player's columns. `base` is where their board is in the SELECT below, `b2b` where their b2b is.
+fn read_player(row: &rusqlite::Row, prefix: &str, base: usize, b2b: usize) -> Result<GameState, RowError> {
+    let column = |name: &str| format!("{}_{}", prefix, name);
+    let piece = |i: usize, name: &str| -> Result<Piece, RowError> {
+        to_piece(&row.get::<_, String>(base + i)?, &column(name))
+    };
+
+    Ok(GameState {
+        board: to_board(row.get(base)?, &column("board"))?,
+        current_piece: piece(1, "current_piece")?,
+
+        placement:Move{
+            move_type: to_optional_piece(&row.get::<_, String>(base + 2)?, &column("move_piece_type"))?,
+            rotation: to_rotation(row.get(base + 3)?, &column("move_piece_rot"))?,
+            x:row.get(base + 4)?,
+            y:row.get(base + 5)?
+        },
+        meter:row.get(base + 6)?,
+        combo:row.get(base + 7)?,
+        b2b:row.get(b2b)?,
+        attack:row.get(base + 8)?,
+        damage_received:row.get(base + 9)?,
+        spun:row.get::<_, i32>(base + 10)? == 1,
+        queue:[
+            piece(11, "queue_0")?,
+            piece(12, "queue_1")?,
+            piece(13, "queue_2")?,
+            piece(14, "queue_3")?,
+            piece(15, "queue_4")?,
+        ],
+        hold: to_optional_piece(&row.get::<_, String>(base + 16)?, &column("hold"))?
+    })
+}
+
+fn read_datum(row: &rusqlite::Row) -> Result<Datum, RowError> {
+    Ok(Datum{
+        p1: read_player(row, "p1", 0, 37)?,
+        p2: read_player(row, "p2", 17, 38)?,
+        state: to_state(&row.get::<_, String>(34)?)?,
+        game_id: row.get(35)?,
+        move_index: row.get(36)?
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OnError {
+    SkipRow,
+    SkipGame,
+    Abort,
+}
+
+impl OnError {
+    fn parse(s: &str) -> Option<OnError> {
+        match s {
+            "skip-row" => Some(OnError::SkipRow),
+            "skip-game" => Some(OnError::SkipGame),
+            "abort" => Some(OnError::Abort),
+            _ => None,
+        }
+    }
+}
+
+// rows that didn't make it into the dataset
+#[derive(Default)]
+struct Rejections {
+    by_reason: BTreeMap<String, usize>,
+    games: BTreeSet<u16>,
+    // good rows dropped along with a bad row's game
+    dropped: usize,
+}
+
+impl Rejections {
+    fn add(&mut self, error: &RowError, game_id: Option<u16>) {
+        *self.by_reason.entry(error.reason()).or_default() += 1;
+        if let Some(game_id) = game_id {
+            self.games.insert(game_id);
+        }
+    }
+
+    fn report(&self) {
+        if self.by_reason.is_empty() {
+            return;
+        }
+
+        let bad: usize = self.by_reason.values().sum();
+        println!("Rejected {} malformed rows, dropped {} more from the same games", bad, self.dropped);
 
-    let conn = Connection::open(&db_path).unwrap();
+        for (reason, n) in &self.by_reason {
+            println!("  {}: {}", reason, n);
+        }
+
+        let games: Vec<String> = self.games.iter().map(|g| g.to_string()).collect();
+        println!("Affected game_ids: {}", games.join(", "));
+    }
+}
+
+// Streams the database in chunks of about `chunk_rows` moves. Chunks are only cut between
+// games, so every game is seen whole by `on_chunk`. Malformed rows are handled according to
+// `on_error` and counted in `rejections`. Returns the number of moves passed on.
+fn extract_data(db_path: &str, chunk_rows: usize, on_error: OnError, rejections: &mut Rejections, mut on_chunk: impl FnMut(&[Datum]) -> DuckResult<()>) -> Result<usize, Box<dyn std::error::Error>> {
+
+    let conn = Connection::open(db_path)?;
     
     let mut stmt = conn.prepare("SELECT 
         p1_board,
@@ -136,166 +284,351 @@ fn extract_data(db_path:String) -> Vec<Datum> {
         move_index,
         p1_b2b,
         p2_b2b
-        FROM Data ORDER BY game_id ASC, move_index ASC").unwrap();
-    let data_iter = stmt.query_map([], |row| {
-        Ok(Datum{
-            p1:GameState {
-                board: to_board(row.get(0)?),
-                current_piece: to_piece(&row.get::<_, String>(1)?).unwrap(),
-                
-                placement:Move{
-                    move_type:to_piece(&row.get::<_, String>(2)?).ok(),
-                    rotation: to_rotation(row.get(3)?).unwrap(),
-                    x:row.get(4)?,
-                    y:row.get(5)?
-                },
-                meter:row.get(6)?,
-                combo:row.get(7)?,
-                b2b:row.get(37)?,
-                attack:row.get(8)?,
-                damage_received:row.get(9)?,
-                spun:row.get::<_, i32>(10)? == 1,
-                queue:[
-                    to_piece(&row.get::<_, String>(11)?).unwrap(),
-                    to_piece(&row.get::<_, String>(12)?).unwrap(),
-                    to_piece(&row.get::<_, String>(13)?).unwrap(),
-                    to_piece(&row.get::<_, String>(14)?).unwrap(),
-                    to_piece(&row.get::<_, String>(15)?).unwrap(),
-                ],
-                hold:to_piece(&row.get::<_, String>(16)?).ok()
-            },
-            p2:GameState {
-                board: to_board(row.get(17)?),
-                current_piece: to_piece(&row.get::<_, String>(18)?).unwrap(),
-                
-                placement:Move{
-                    move_type:to_piece(&row.get::<_, String>(19)?).ok(),
-                    rotation: to_rotation(row.get(20)?).unwrap(),
-                    x:row.get(21)?,
-                    y:row.get(22)?
-                },
-                meter:row.get(23)?,
-                combo:row.get(24)?,
-                b2b:row.get(38)?,
-                attack:row.get(25)?,
-                damage_received:row.get(26)?,
-                spun:row.get::<_, i32>(27)? == 1,
-                queue:[
-                    to_piece(&row.get::<_, String>(28)?).unwrap(),
-                    to_piece(&row.get::<_, String>(29)?).unwrap(),
-                    to_piece(&row.get::<_, String>(30)?).unwrap(),
-                    to_piece(&row.get::<_, String>(31)?).unwrap(),
-                    to_piece(&row.get::<_, String>(32)?).unwrap(),
-                ],
-                hold:to_piece(&row.get::<_, String>(33)?).ok()
-            },
-            state:to_state(&row.get::<_, String>(34)?).unwrap(),
-            game_id: row.get(35)?,
-            move_index: row.get(36)?
-        })
-    }).unwrap();
-    
-    return data_iter.map(|e|e.unwrap()).collect();
+        FROM Data ORDER BY game_id ASC, move_index ASC")?;
+
+    let mut rows = stmt.query([])?;
+    let mut chunk: Vec<Datum> = Vec::with_capacity(chunk_rows);
+    let mut total = 0;
+    // game currently being thrown away under skip-game
+    let mut skipping: Option<u16> = None;
+
+    while let Some(row) = rows.next()? {
+        let game_id: Option<u16> = row.get(35).ok();
+
+        if skipping.is_some() && skipping == game_id {
+            rejections.dropped += 1;
+            continue;
+        }
+
+        let datum = match read_datum(row) {
+            Ok(datum) => datum,
+            Err(e) => {
+                rejections.add(&e, game_id);
+
+                let move_index: Option<u16> = row.get(36).ok();
+                let at = format!("game_id {:?} move_index {:?}", game_id, move_index);
+
+                match on_error {
+                    OnError::Abort => return Err(format!("malformed row at {}: {}", at, e).into()),
+                    OnError::SkipRow => {}
+                    OnError::SkipGame => {
+                        if let Some(game_id) = game_id {
+                            let before = chunk.len();
+                            chunk.retain(|d| d.game_id != game_id);
+                            rejections.dropped += before - chunk.len();
+                            skipping = Some(game_id);
+                        }
+                    }
+                }
+                continue;
+            }
+        };
+
+        if chunk.len() >= chunk_rows && chunk.last().is_some_and(|last| last.game_id != datum.game_id) {
+            on_chunk(&chunk)?;
+            total += chunk.len();
+            chunk.clear();
+        }
+
+        chunk.push(datum);
+    }
+
+    if !chunk.is_empty() {
+        on_chunk(&chunk)?;
+        total += chunk.len();
+    }
+
+    Ok(total)
 }
 
-fn create_dataset(data: &[Datum], output_db_path: &str) -> DuckResult<()> {
-    let start = Instant::now();
+// rows are written here chunk by chunk and only become training_data once every chunk is in
+const staging_table: &str = "training_data_staging";
 
+// Fresh, empty staging table. The training_data already in the file stays as it is until
+// finish_dataset swaps the staging table in.
+fn create_dataset(output_db_path: &str) -> DuckResult<DuckConnection> {
     let conn = DuckConnection::open(output_db_path)?;
 
-    conn.execute("DROP TABLE IF EXISTS training_data", [])?;
+    conn.execute(&format!("DROP TABLE IF EXISTS {}", staging_table), [])?;
 
     conn.execute(
         &format!(
-            "CREATE TABLE IF NOT EXISTS training_data (
+            "CREATE TABLE {} (
                 game_id       INTEGER NOT NULL,
                 move_index    INTEGER NOT NULL,
+                mirrored      BOOLEAN NOT NULL,
                 state         SMALLINT NOT NULL,
-                ground_truth  REAL NOT NULL,
+                ground_truth  REAL,
                 {},
                 {},
-                PRIMARY KEY (game_id, move_index)
+                PRIMARY KEY (game_id, move_index, mirrored)
             )",
+            staging_table,
             Features::sql_columns_with_types("p1"),
             Features::sql_columns_with_types("p2"),
         ),
         [],
     )?;
 
-    let mut rows: Vec<Row> = data.par_iter()
-        .map(|d| {
-            let p1_attrs = features::feature_extractor::extract_features(&d.p1);
-            let p2_attrs = features::feature_extractor::extract_features(&d.p2);
-
-            Row {
-                features: (p1_attrs, p2_attrs),
-                state: d.state,
-                game_id: d.game_id,
-                move_index: d.move_index,
-                ground_truth: to_death_value(&d.state).unwrap(),
+    Ok(conn)
+}
+
+// runs `f` in a transaction of its own, rolled back if it fails
+fn in_transaction(conn: &DuckConnection, f: impl FnOnce() -> DuckResult<()>) -> DuckResult<()> {
+    conn.execute_batch("BEGIN TRANSACTION")?;
+
+    match f() {
+        Ok(()) => conn.execute_batch("COMMIT"),
+        Err(e) => {
+            if let Err(e) = conn.execute_batch("ROLLBACK") {
+                println!("Could not roll back: {}", e);
             }
-        })
-        .collect();
+            Err(e)
+        }
+    }
+}
+
+// Replaces training_data with the staging table and writes the feature manifest and
+// `metadata`, all in one transaction. Then writes the JSON manifest next to the dataset.
+fn finish_dataset(conn: &DuckConnection, output_db_path: &str, metadata: &[(&str, String)]) -> DuckResult<()> {
+    in_transaction(conn, || {
+        conn.execute("DROP TABLE IF EXISTS training_data", [])?;
+        conn.execute(&format!("ALTER TABLE {} RENAME TO training_data", staging_table), [])?;
 
-    let mut loss = 1f32;
-    for row in rows.iter_mut().rev() {
-        if row.ground_truth != 0f32 {
-            loss = row.ground_truth;
-        } else {
-            row.ground_truth = (50f32 / 60f32) * loss;
-            loss = row.ground_truth;
+        manifest::write_duckdb(conn)?;
+        for (key, value) in metadata {
+            manifest::set_metadata(conn, key, value)?;
         }
+
+        Ok(())
+    })?;
+
+    match manifest::write_json(output_db_path) {
+        Ok(path) => println!("Wrote feature manifest to {}", path),
+        Err(e) => println!("Could not write feature manifest: {}", e),
     }
 
-    let mut duration = start.elapsed().as_secs_f64();
+    Ok(())
+}
 
-    println!(
-        "Feature extraction took {:.1}s",
-        duration
-    );
-    
-    let mut record_batch = rows_to_record_batch(&rows).unwrap();
+fn to_row(d: &Datum, mirrored: bool) -> Row {
+    let p1_attrs = features::feature_extractor::extract_features(&d.p1);
+    let p2_attrs = features::feature_extractor::extract_features(&d.p2);
 
-    duration = start.elapsed().as_secs_f64();
-    
-    println!(
-        "Record Batch preparation took {:.1}s",
-        duration
-    );
+    Row {
+        features: (p1_attrs, p2_attrs),
+        state: d.state,
+        game_id: d.game_id,
+        move_index: d.move_index,
+        mirrored,
+        ground_truth: None,
+    }
+}
 
-    let mut appender = conn.appender("training_data")?;
+// Extracts features for one chunk of whole games in parallel and appends them to the staging
+// table, committing them on their own. With `mirror` the chunk's mirror images are appended
+// too, with the same ground_truth as their originals.
+fn append_chunk(conn: &DuckConnection, data: &[Datum], target: &Target, mirror: bool) -> DuckResult<()> {
+    let mut rows: Vec<Row> = data.par_iter()
+        .map(|d| to_row(d, false))
+        .collect();
 
-    appender.append_record_batch(record_batch)?;
-    appender.flush()?;
+    target.label_rows(&mut rows);
 
-    duration = start.elapsed().as_secs_f64();
+    if mirror {
+        let mirrored: Vec<Row> = data.par_iter()
+            .zip(&rows)
+            .map(|(d, row)| Row { ground_truth: row.ground_truth, ..to_row(&mirror_datum(d), true) })
+            .collect();
 
-    println!(
-        "Wrote {} training records to {} in {:.1}s",
-        data.len(),
-        output_db_path,
-        duration
-    );
+        rows.extend(mirrored);
+    }
 
-    Ok(())
+    let record_batch = rows_to_record_batch(&rows).unwrap();
+
+    in_transaction(conn, || {
+        let mut appender = conn.appender(staging_table)?;
+
+        appender.append_record_batch(record_batch)?;
+        appender.flush()
+    })
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+// removes `--name` from anywhere in `args` and says whether it was there
+fn take_switch(args: &mut Vec<String>, name: &str) -> bool {
+    let before = args.len();
+    args.retain(|a| a != name);
+    args.len() != before
+}
+
+// removes `--name value` from anywhere in `args` and returns the value
+fn take_flag(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == name)?;
+    let value = args.get(i + 1).cloned().unwrap_or_default();
+    args.drain(i..(i + 2).min(args.len()));
+    Some(value)
+}
+
+// features relabel <training database> [model] [--lambda 0.5] [--column td_target]
+fn run_relabel(mut args: Vec<String>) -> ExitCode {
+    let lambda = match take_flag(&mut args, "--lambda").map(|s| s.parse::<f32>()) {
+        None => relabel::default_lambda,
+        Some(Ok(lambda)) => lambda,
+        Some(Err(_)) => {
+            println!("--lambda takes a number in [0, 1), e.g. 0.5.");
+            return ExitCode::FAILURE;
+        }
+    };
+    let column = take_flag(&mut args, "--column").unwrap_or_else(|| relabel::default_column.to_string());
+
+    let Some(db_path) = args.get(2) else {
+        println!("Please provide a training database path.");
+        return ExitCode::FAILURE;
+    };
+    if !exists(db_path).unwrap() {
+        println!("Training database not found.");
+        return ExitCode::FAILURE;
+    }
+    let model_path = args.get(3).map_or("models/model.ubj", String::as_str);
+
+    let start = Instant::now();
+
+    let model = match TreeModel::load(model_path) {
+        Ok(model) => model,
+        Err(e) => {
+            println!("Error loading {}: {}", model_path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match relabel::relabel(db_path, &model, model_path, lambda, &column, default_chunk_rows) {
+        Ok(total) => {
+            println!(
+                "Wrote {} for {} training records with lambda {} in {:.1}s",
+                column,
+                total,
+                lambda,
+                start.elapsed().as_secs_f64()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("Error relabelling dataset: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().collect();
+
+    if args.get(1).is_some_and(|a| a == "relabel") {
+        return run_relabel(args);
+    }
+
+    // --on-error skip-row|skip-game|abort
+    let on_error = match take_flag(&mut args, "--on-error") {
+        None => OnError::Abort,
+        Some(s) => match OnError::parse(&s) {
+            Some(policy) => policy,
+            None => {
+                println!("--on-error takes skip-row, skip-game or abort.");
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    // --target discount[:factor] | window:n[:factor] | ttd:horizon, --draw value
+    let mut target = Target::default();
+    if let Some(s) = take_flag(&mut args, "--target") {
+        match Scheme::parse(&s) {
+            Ok(scheme) => target.scheme = scheme,
+            Err(e) => {
+                println!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if let Some(s) = take_flag(&mut args, "--draw") {
+        match s.parse() {
+            Ok(draw) => target.draw = draw,
+            Err(_) => {
+                println!("--draw takes the value of a drawn game, e.g. 0.");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    // --mirror adds a left-right mirrored copy of every position
+    let mirror = take_switch(&mut args, "--mirror");
+
     if args.len() == 1 {
         println!("Please provide an input database path.");
-        return;
+        return ExitCode::FAILURE;
     }
     if args.len() == 2 {
         println!("Please provide an output database path.");
-        return;
+        return ExitCode::FAILURE;
     }
     if !exists(args[1].to_string()).unwrap() {
         println!("Input database not found.");
-        return;
+        return ExitCode::FAILURE;
     }
-    let data = extract_data(args[1].to_string());
-    if let Err(e) = create_dataset(&data, &args[2].to_string()) {
-        println!("Error creating dataset: {}", e);
+    let chunk_rows = match args.get(3).map(|s| s.parse::<usize>()) {
+        None => default_chunk_rows,
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            println!("Chunk size must be a positive number of rows.");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let start = Instant::now();
+
+    let conn = match create_dataset(&args[2]) {
+        Ok(conn) => conn,
+        Err(e) => {
+            println!("Error creating dataset: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let metadata = [
+        ("target", target.scheme.to_string()),
+        ("target_draw", target.draw.to_string()),
+        ("mirrored", mirror.to_string()),
+    ];
+
+    let mut written = 0;
+    let mut rejections = Rejections::default();
+    let result = extract_data(&args[1], chunk_rows, on_error, &mut rejections, |chunk| {
+        append_chunk(&conn, chunk, &target, mirror)?;
+        written += chunk.len() * if mirror { 2 } else { 1 };
+        println!("Wrote {} training records ({:.1}s)", written, start.elapsed().as_secs_f64());
+        Ok(())
+    });
+
+    // on any error the staging table is dropped, so a partial dataset never replaces the old one
+    let result = result.and_then(|_| Ok(finish_dataset(&conn, &args[2], &metadata)?));
+
+    rejections.report();
+
+    match result {
+        Ok(()) => {
+            println!(
+                "Wrote {} training records to {} in {:.1}s",
+                written,
+                args[2],
+                start.elapsed().as_secs_f64()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            if let Err(e) = conn.execute(&format!("DROP TABLE IF EXISTS {}", staging_table), []) {
+                println!("Could not drop {}: {}", staging_table, e);
+            }
+            println!("Error creating dataset, training_data was left as it was: {}", e);
+            ExitCode::FAILURE
+        }
     }
 }
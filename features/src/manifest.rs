@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::Path;
+
+use duckdb::{Connection as DuckConnection, Result as DuckResult, params};
+use serde_json::{Value, json};
+
+use crate::feature_extractor::{Features, feature_set_version};
+use crate::whitelist;
+
+/*
+    Describes the feature columns of a dataset, so the Python trainers and
+    the Rust inference side can check they agree with the extractor that
+    produced the data. Written next to the dataset as JSON and into the
+    DuckDB file as the `feature_manifest` and `dataset_metadata` tables.
+*/
+
+pub const dtype: &str = "int16";
+
+// FNV-1a over the whitelisted pattern ids
+fn whitelist_hash(list: &[usize]) -> String {
+    let mut h: u64 = 0xcbf29ce484222325;
+
+    for &i in list {
+        for byte in (i as u64).to_le_bytes() {
+            h ^= byte as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    format!("{:016x}", h)
+}
+
+pub fn whitelist_hashes() -> Vec<(&'static str, String)> {
+    vec![
+        ("top_100_3x3s", whitelist_hash(&whitelist::top_100_3x3s)),
+        ("top_100_3x3s_with_x", whitelist_hash(&whitelist::top_100_3x3s_with_x)),
+        ("top_100_3x3s_with_y", whitelist_hash(&whitelist::top_100_3x3s_with_y)),
+    ]
+}
+
+// model input columns in order, p1 then p2
+pub fn input_columns() -> Vec<String> {
+    let mut columns = Features::column_names("p1");
+    columns.extend(Features::column_names("p2"));
+    columns
+}
+
+pub fn manifest() -> Value {
+    let hashes: serde_json::Map<String, Value> = whitelist_hashes()
+        .into_iter()
+        .map(|(name, hash)| (name.to_string(), Value::String(hash)))
+        .collect();
+
+    json!({
+        "feature_set_version": feature_set_version,
+        "dtype": dtype,
+        "count": input_columns().len(),
+        "columns": input_columns(),
+        "whitelist_hashes": hashes,
+    })
+}
+
+// `<dataset>.manifest.json` next to the dataset
+pub fn write_json(dataset_path: &str) -> std::io::Result<String> {
+    let path = Path::new(dataset_path).with_extension("manifest.json");
+
+    fs::write(&path, serde_json::to_string_pretty(&manifest()).unwrap())?;
+
+    Ok(path.to_string_lossy().into_owned())
+}
+
+pub fn write_duckdb(conn: &DuckConnection) -> DuckResult<()> {
+    conn.execute("DROP TABLE IF EXISTS feature_manifest", [])?;
+    conn.execute("DROP TABLE IF EXISTS dataset_metadata", [])?;
+    conn.execute(
+        "CREATE TABLE feature_manifest (
+            position INTEGER NOT NULL PRIMARY KEY,
+            name     TEXT NOT NULL,
+            dtype    TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    let mut appender = conn.appender("feature_manifest")?;
+    for (position, name) in input_columns().iter().enumerate() {
+        appender.append_row(params![position as i32, name, dtype])?;
+    }
+    appender.flush()?;
+
+    set_metadata(conn, "feature_set_version", &feature_set_version.to_string())?;
+    for (name, hash) in whitelist_hashes() {
+        set_metadata(conn, &format!("whitelist_hash_{}", name), &hash)?;
+    }
+
+    Ok(())
+}
+
+// key/value pairs describing how the dataset was made
+pub fn set_metadata(conn: &DuckConnection, key: &str, value: &str) -> DuckResult<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS dataset_metadata (
+            key   TEXT NOT NULL PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute("INSERT OR REPLACE INTO dataset_metadata VALUES (?, ?)", params![key, value])?;
+
+    Ok(())
+}
+
+// Compare the feature names a model was trained on with what this build extracts.
+// Returns the first position that differs.
+pub fn check_columns(names: &[String]) -> Result<(), String> {
+    let expected = input_columns();
+
+    if names.len() != expected.len() {
+        return Err(format!("model has {} columns, extractor produces {}", names.len(), expected.len()));
+    }
+
+    match names.iter().zip(&expected).position(|(a, b)| a != b) {
+        Some(i) => Err(format!("column {} is {:?} in the model but {:?} in the extractor", i, names[i], expected[i])),
+        None => Ok(()),
+    }
+}
+
+// Where each of a model's feature names is in input_columns(). A model trained before columns
+// were added can still be fed from feature_vector, as long as every column it uses still exists.
+pub fn column_positions(names: &[String]) -> Result<Vec<usize>, String> {
+    let expected = input_columns();
+
+    names.iter()
+        .map(|name| {
+            expected.iter()
+                .position(|column| column == name)
+                .ok_or_else(|| format!("model column {:?} is not produced by the extractor", name))
+        })
+        .collect()
+}
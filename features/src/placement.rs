@@ -0,0 +1,290 @@
+use tetris::board::Board;
+use tetris::moves::Move;
+use tetris::piece::{Piece, Rotation};
+
+use crate::game::{self, GameState};
+
+// SRS mino offsets around the rotation centre for the north orientation, y pointing up
+fn north_minos(piece: Piece) -> [(i8, i8); 4] {
+    match piece {
+        Piece::I => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+        Piece::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        Piece::T => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+        Piece::L => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+        Piece::J => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+        Piece::S => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+        Piece::Z => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+    }
+}
+
+pub fn minos(piece: Piece, rotation: Rotation) -> [(i8, i8); 4] {
+    north_minos(piece).map(|(x, y)| match rotation {
+        Rotation::North => (x, y),
+        Rotation::East => (y, -x),
+        Rotation::South => (-x, -y),
+        Rotation::West => (-y, x),
+    })
+}
+
+pub fn cells_at(piece: Piece, rotation: Rotation, x: i8, y: i8) -> [(i8, i8); 4] {
+    minos(piece, rotation).map(|(dx, dy)| (x + dx, y + dy))
+}
+
+pub fn cells(mv: &Move) -> [(i8, i8); 4] {
+    cells_at(mv.kind, mv.r, mv.x, mv.y)
+}
+
+// walls and floor count as filled
+pub fn occupied(board: &Board, x: i8, y: i8) -> bool {
+    if x < 0 || x > 9 || y < 0 {
+        return true;
+    }
+    if y > 63 {
+        return false;
+    }
+    (board.cols[x as usize] >> y) & 1 == 1
+}
+
+pub fn fits(board: &Board, mv: &Move) -> bool {
+    cells(mv).iter().all(|&(x, y)| !occupied(board, x, y))
+}
+
+pub fn place(board: &mut Board, mv: &Move) {
+    for (x, y) in cells(mv) {
+        board.set(x, y);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+// the two corners on the side the T points to
+fn front_corners(rotation: Rotation) -> [(i8, i8); 2] {
+    match rotation {
+        Rotation::North => [(-1, 1), (1, 1)],
+        Rotation::East => [(1, 1), (1, -1)],
+        Rotation::South => [(-1, -1), (1, -1)],
+        Rotation::West => [(-1, 1), (-1, -1)],
+    }
+}
+
+// Checked before the piece is placed. movegen sets `tspin` on placements whose last input
+// was a rotation, a T dropped straight into a notch is never a spin. The three corner rule
+// is checked on top of that, and the two front corners tell a full spin from a mini.
+pub fn spin(board: &Board, mv: &Move) -> Spin {
+    if mv.kind != Piece::T || mv.tspin.is_none() {
+        return Spin::None;
+    }
+
+    let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+
+    let filled = corners.iter()
+        .filter(|&&(dx, dy)| occupied(board, mv.x + dx, mv.y + dy))
+        .count();
+
+    if filled < 3 {
+        return Spin::None;
+    }
+
+    if front_corners(mv.r).iter().all(|&(dx, dy)| occupied(board, mv.x + dx, mv.y + dy)) {
+        Spin::Full
+    } else {
+        Spin::Mini
+    }
+}
+
+// full or mini
+pub fn is_tspin(board: &Board, mv: &Move) -> bool {
+    spin(board, mv) != Spin::None
+}
+
+// removes every full row and returns how many were cleared
+pub fn clear_lines(board: &mut Board) -> u32 {
+    let full = board.cols.iter().fold(u64::MAX, |acc, col| acc & col);
+
+    if full == 0 {
+        return 0;
+    }
+
+    for col in board.cols.iter_mut() {
+        let mut rows = full;
+        // top down so lower row indices stay valid
+        while rows != 0 {
+            let y = 63 - rows.leading_zeros();
+            let below = *col & ((1u64 << y) - 1);
+            let above = col.checked_shr(y + 1).unwrap_or(0) << y;
+            *col = below | above;
+            rows &= !(1u64 << y);
+        }
+    }
+
+    full.count_ones()
+}
+
+// shift the queue by `n`, pulling new pieces from `next`
+fn advance_queue(queue: &[Piece; 5], n: usize, next: &mut impl FnMut() -> Piece) -> [Piece; 5] {
+    let mut ret = *queue;
+
+    for _ in 0..n {
+        ret.rotate_left(1);
+        ret[4] = next();
+    }
+
+    ret
+}
+
+// Plays `mv` from `state`. Moves whose piece is not the current piece go through hold.
+// Returns the new state and the number of cleared lines. Attack and garbage are left
+// to the caller, only combo, b2b and spun are updated here.
+pub fn play(state: &GameState, mv: &Move, mut next: impl FnMut() -> Piece) -> (GameState, u32) {
+    let mut ret = state.clone();
+
+    let held = mv.kind != state.current_piece;
+
+    if held {
+        match state.hold {
+            Some(_) => {
+                ret.hold = Some(state.current_piece);
+                ret.current_piece = state.queue[0];
+                ret.queue = advance_queue(&state.queue, 1, &mut next);
+            }
+            None => {
+                ret.hold = Some(state.current_piece);
+                ret.current_piece = state.queue[1];
+                ret.queue = advance_queue(&state.queue, 2, &mut next);
+            }
+        }
+    } else {
+        ret.current_piece = state.queue[0];
+        ret.queue = advance_queue(&state.queue, 1, &mut next);
+    }
+
+    let tspin = is_tspin(&state.board, mv);
+
+    place(&mut ret.board, mv);
+    let lines = clear_lines(&mut ret.board);
+
+    if lines > 0 {
+        ret.combo = ret.combo.saturating_add(1);
+        if lines == 4 || tspin {
+            ret.b2b = ret.b2b.saturating_add(1);
+        } else {
+            ret.b2b = 0;
+        }
+    } else {
+        ret.combo = 0;
+    }
+
+    ret.spun = tspin;
+    ret.attack = 0;
+    ret.placement = game::Move {
+        move_type: Some(mv.kind),
+        rotation: mv.r,
+        x: mv.x as u8,
+        y: mv.y as u8,
+    };
+
+    (ret, lines)
+}
+
+// the piece a hold move would play, if there is one
+pub fn hold_piece(state: &GameState) -> Piece {
+    state.hold.unwrap_or(state.queue[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tetris::movegen::movegen;
+
+    // T rotated east before it drops down the left wall, its nub resting on (1, 0).
+    // Three corners are filled, counting the wall.
+    fn wall_slot() -> (Board, Move) {
+        let mut board = Board::new();
+        board.set(1, 0);
+
+        (board, Move { x: 0, y: 1, r: Rotation::East, kind: Piece::T, tspin: None })
+    }
+
+    #[test]
+    fn corners_alone_are_not_a_spin() {
+        let (board, mv) = wall_slot();
+
+        assert!(fits(&board, &mv));
+        assert_eq!(spin(&board, &mv), Spin::None);
+        assert!(!is_tspin(&board, &mv));
+    }
+
+    #[test]
+    fn dropped_t_does_not_set_spun() {
+        let (board, mv) = wall_slot();
+        let mut state = GameState::new(Piece::T, [Piece::I; 5]);
+        state.board = board;
+
+        let (after, lines) = play(&state, &mv, || Piece::I);
+
+        assert_eq!(lines, 0);
+        assert!(!after.spun);
+        assert_eq!(after.b2b, 0);
+    }
+
+    #[test]
+    fn clear_lines_drops_the_rows_above() {
+        let mut board = Board::new();
+        for x in 0..10 {
+            board.set(x, 0);
+            board.set(x, 2);
+        }
+        board.set(3, 1);
+        board.set(5, 3);
+
+        assert_eq!(clear_lines(&mut board), 2);
+        assert_eq!(board.cols[3], 0b1);
+        assert_eq!(board.cols[5], 0b10);
+        assert_eq!(board.cols.iter().map(|c| c.count_ones()).sum::<u32>(), 2);
+    }
+
+    #[test]
+    fn first_hold_draws_two_pieces() {
+        let state = GameState::new(Piece::T, [Piece::I, Piece::O, Piece::S, Piece::Z, Piece::L]);
+        let mv = Move { x: 1, y: 0, r: Rotation::North, kind: Piece::I, tspin: None };
+
+        let (after, _) = play(&state, &mv, || Piece::J);
+
+        assert_eq!(after.hold, Some(Piece::T));
+        assert_eq!(after.current_piece, Piece::O);
+        assert_eq!(after.queue, [Piece::S, Piece::Z, Piece::L, Piece::J, Piece::J]);
+
+        let mv = Move { x: 1, y: 1, r: Rotation::North, kind: Piece::T, tspin: None };
+        let (again, _) = play(&after, &mv, || Piece::I);
+
+        assert_eq!(again.hold, Some(Piece::O));
+        assert_eq!(again.current_piece, Piece::S);
+        assert_eq!(again.queue, [Piece::Z, Piece::L, Piece::J, Piece::J, Piece::I]);
+    }
+
+    #[test]
+    fn movegen_placements_fit_and_rest() {
+        let mut board = Board::new();
+        for x in 0..9 {
+            board.set(x, 0);
+        }
+        board.set(2, 1);
+
+        for piece in [Piece::I, Piece::O, Piece::T, Piece::L, Piece::J, Piece::S, Piece::Z] {
+            let moves = movegen(&board, piece);
+            assert!(!moves.is_empty());
+
+            for mv in moves {
+                assert!(fits(&board, &mv), "{:?}", mv);
+                let lower = Move { y: mv.y - 1, ..mv };
+                assert!(!fits(&board, &lower), "{:?} floats", mv);
+            }
+        }
+    }
+}
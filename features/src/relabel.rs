@@ -0,0 +1,166 @@
+use duckdb::{Connection as DuckConnection, params};
+use rayon::prelude::*;
+
+use crate::game::State;
+use crate::manifest;
+use crate::target::td_lambda;
+use crate::xgboost::TreeModel;
+
+/*
+    Rewrites the value targets of an existing training_data table with
+    TD(λ), using a trained model's predictions on every row. This is the
+    refinement step of train/td_lambda.py, done one chunk of whole games at
+    a time so the table never has to fit in memory. Mirrored copies are
+    relabelled as games of their own.
+
+    The targets go into their own REAL column (td_target by default) next to
+    ground_truth, and λ, the model and the column are recorded in
+    dataset_metadata.
+*/
+
+pub const default_lambda: f32 = 0.5;
+pub const default_column: &str = "td_target";
+
+// scratch table the targets are staged in before the UPDATE
+const staging_table: &str = "td_relabel";
+
+struct Position {
+    game_id: u16,
+    move_index: u16,
+    mirrored: bool,
+    state: State,
+    features: Vec<f32>,
+}
+
+fn to_state(value: i16) -> Result<State, String> {
+    Ok(match value {
+        0 => State::PLAYING,
+        1 => State::P1_WIN,
+        2 => State::P2_WIN,
+        3 => State::DRAW,
+        _ => return Err(format!("state {} is not a game state", value)),
+    })
+}
+
+fn check_column_name(column: &str) -> Result<(), String> {
+    let valid = column.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && column.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    match column {
+        _ if !valid => Err(format!("{:?} is not a usable column name", column)),
+        "game_id" | "move_index" | "mirrored" | "state" | "ground_truth" => Err(format!("will not overwrite {}", column)),
+        _ if manifest::input_columns().iter().any(|c| c == column) => Err(format!("{} is a feature column", column)),
+        _ => Ok(()),
+    }
+}
+
+// predicts every position of the chunk and stages the TD(λ) targets of each game
+fn stage_chunk(appender: &mut duckdb::Appender, model: &TreeModel, chunk: &[Position], lambda: f32) -> Result<(), Box<dyn std::error::Error>> {
+    let predictions: Vec<f32> = chunk.par_iter().map(|p| model.predict(&p.features)).collect();
+
+    let mut start = 0;
+    for game in chunk.chunk_by(|a, b| (a.game_id, a.mirrored) == (b.game_id, b.mirrored)) {
+        let end = start + game.len();
+        let states: Vec<State> = game.iter().map(|p| p.state).collect();
+
+        for (position, target) in game.iter().zip(td_lambda(&predictions[start..end], &states, lambda)) {
+            appender.append_row(params![position.game_id, position.move_index, position.mirrored, target])?;
+        }
+
+        start = end;
+    }
+
+    Ok(())
+}
+
+// Relabels `training_data` in the DuckDB file at `db_path`. Returns the number of rows labelled.
+pub fn relabel(db_path: &str, model: &TreeModel, model_name: &str, lambda: f32, column: &str, chunk_rows: usize) -> Result<usize, Box<dyn std::error::Error>> {
+    if !(0.0..1.0).contains(&lambda) {
+        return Err(format!("lambda must be in [0, 1), got {}", lambda).into());
+    }
+    check_column_name(column)?;
+
+    // only the columns the model was trained on, in its order
+    let all_columns = manifest::input_columns();
+    let columns: Vec<&str> = model.inputs()?.iter().map(|&i| all_columns[i].as_str()).collect();
+
+    let conn = DuckConnection::open(db_path)?;
+
+    conn.execute(&format!("DROP TABLE IF EXISTS {}", staging_table), [])?;
+    conn.execute(
+        &format!(
+            "CREATE TABLE {} (
+                game_id    INTEGER NOT NULL,
+                move_index INTEGER NOT NULL,
+                mirrored   BOOLEAN NOT NULL,
+                value      REAL NOT NULL
+            )",
+            staging_table
+        ),
+        [],
+    )?;
+
+    // the read stays open while targets are appended, so they go through a second connection
+    let writer = conn.try_clone()?;
+    let mut appender = writer.appender(staging_table)?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT game_id, move_index, mirrored, state, {} FROM training_data ORDER BY game_id ASC, mirrored ASC, move_index ASC",
+        columns.join(", ")
+    ))?;
+    let mut rows = stmt.query([])?;
+
+    let width = columns.len();
+    let mut chunk: Vec<Position> = Vec::with_capacity(chunk_rows);
+    let mut total = 0;
+
+    while let Some(row) = rows.next()? {
+        let mut features = Vec::with_capacity(width);
+        for i in 0..width {
+            features.push(row.get::<_, i16>(4 + i)? as f32);
+        }
+
+        let position = Position {
+            game_id: row.get(0)?,
+            move_index: row.get(1)?,
+            mirrored: row.get(2)?,
+            state: to_state(row.get(3)?)?,
+            features,
+        };
+
+        // a game's mirror image comes straight after it, so the cut waits for both
+        if chunk.len() >= chunk_rows && chunk.last().is_some_and(|last| last.game_id != position.game_id) {
+            stage_chunk(&mut appender, model, &chunk, lambda)?;
+            total += chunk.len();
+            chunk.clear();
+        }
+
+        chunk.push(position);
+    }
+
+    if !chunk.is_empty() {
+        stage_chunk(&mut appender, model, &chunk, lambda)?;
+        total += chunk.len();
+    }
+
+    appender.flush()?;
+    drop(appender);
+
+    conn.execute(&format!("ALTER TABLE training_data ADD COLUMN IF NOT EXISTS {} REAL", column), [])?;
+    conn.execute(
+        &format!(
+            "UPDATE training_data SET {column} = t.value FROM {staging} t
+             WHERE training_data.game_id = t.game_id AND training_data.move_index = t.move_index
+               AND training_data.mirrored = t.mirrored",
+            column = column,
+            staging = staging_table
+        ),
+        [],
+    )?;
+    conn.execute(&format!("DROP TABLE {}", staging_table), [])?;
+
+    manifest::set_metadata(&conn, &format!("{}_lambda", column), &lambda.to_string())?;
+    manifest::set_metadata(&conn, &format!("{}_model", column), model_name)?;
+
+    Ok(total)
+}
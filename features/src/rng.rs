@@ -0,0 +1,200 @@
+use tetris::piece::Piece;
+
+/*
+    7-bag piece generator matching bots/util/rng.hpp. Same LCG and bag
+    shuffle, but seeded by the caller instead of std::random_device so
+    games and searches can be replayed.
+
+    A bag mask has bit i set when bag_order[i] has not been dealt from the
+    current bag yet. 0 means the current bag is used up and the next piece
+    starts a fresh one.
+*/
+
+pub const bag_order: [Piece; 7] = [Piece::S, Piece::Z, Piece::J, Piece::L, Piece::T, Piece::O, Piece::I];
+
+pub const full_bag: u8 = 0x7f;
+
+fn bit(piece: Piece) -> u8 {
+    1 << bag_order.iter().position(|&p| p == piece).unwrap()
+}
+
+// pieces that can be dealt next from a bag in state `bag_mask`
+pub fn possible_next(bag_mask: u8) -> Vec<Piece> {
+    let mask = if bag_mask & full_bag == 0 { full_bag } else { bag_mask };
+
+    (0..7)
+        .filter(|&i| (mask >> i) & 1 == 1)
+        .map(|i| bag_order[i])
+        .collect()
+}
+
+// the bag after dealing `piece`, None if the bag could not have produced it
+pub fn deal(bag_mask: u8, piece: Piece) -> Option<u8> {
+    let mask = if bag_mask & full_bag == 0 { full_bag } else { bag_mask };
+
+    if mask & bit(piece) == 0 {
+        return None;
+    }
+
+    Some(mask & !bit(piece))
+}
+
+// The bag after dealing every piece of `queue` in order, starting from `bag_mask`.
+// None if the queue is not a valid 7-bag sequence from there.
+pub fn deal_all(bag_mask: u8, queue: &[Piece]) -> Option<u8> {
+    queue.iter().try_fold(bag_mask, |mask, &piece| deal(mask, piece))
+}
+
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u32,
+    bag: [Piece; 7],
+    index: usize,
+}
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        let mut rng = Rng { state: seed, bag: bag_order, index: 0 };
+        rng.make_bag();
+        rng
+    }
+
+    // resume partway through a bag, the remaining pieces come out in a seeded order
+    pub fn with_bag(seed: u32, bag_mask: u8) -> Self {
+        let mut rng = Rng::new(seed);
+
+        if bag_mask & full_bag == 0 {
+            return rng;
+        }
+
+        let remaining = possible_next(bag_mask);
+        let start = 7 - remaining.len();
+        rng.bag[start..].copy_from_slice(&remaining);
+
+        for i in (start..7).rev() {
+            let j = start + rng.rand((i - start + 1) as u32) as usize;
+            rng.bag.swap(i, j);
+        }
+        rng.index = start;
+
+        rng
+    }
+
+    pub fn next_piece(&mut self) -> Piece {
+        if self.index == 7 {
+            self.make_bag();
+        }
+        let piece = self.bag[self.index];
+        self.index += 1;
+        piece
+    }
+
+    // pieces not yet dealt from the current bag
+    pub fn bag_mask(&self) -> u8 {
+        self.bag[self.index..].iter().fold(0, |mask, &piece| mask | bit(piece))
+    }
+
+    // what the next call to next_piece could return, without peeking at the shuffle
+    pub fn possible_next(&self) -> Vec<Piece> {
+        possible_next(self.bag_mask())
+    }
+
+    // column of the hole in a garbage row, like test.cpp does it
+    pub fn garbage_hole(&mut self) -> usize {
+        self.rand(10) as usize
+    }
+
+    fn rand(&mut self, upper_bound: u32) -> u32 {
+        self.state = self.state.wrapping_mul(0x5d588b65).wrapping_add(0x269ec3);
+        let value = self.state >> 16;
+        if upper_bound != 0 {
+            (value * upper_bound) >> 16
+        } else {
+            value
+        }
+    }
+
+    fn make_bag(&mut self) {
+        self.index = 0;
+        let mut pieces = bag_order;
+
+        for i in (0..7).rev() {
+            let j = self.rand(i as u32 + 1) as usize;
+            self.bag[i] = pieces[j];
+            pieces.swap(j, i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut pieces: Vec<Piece>) -> Vec<Piece> {
+        pieces.sort_by_key(|&p| bit(p));
+        pieces
+    }
+
+    #[test]
+    fn every_bag_holds_each_piece_once() {
+        let mut rng = Rng::new(12345);
+
+        for _ in 0..4 {
+            let bag: Vec<Piece> = (0..7).map(|_| rng.next_piece()).collect();
+            assert_eq!(sorted(bag), bag_order.to_vec());
+            assert_eq!(rng.bag_mask(), 0);
+        }
+    }
+
+    #[test]
+    fn bag_mask_tracks_what_is_left() {
+        let mut rng = Rng::new(7);
+        assert_eq!(rng.bag_mask(), full_bag);
+
+        for dealt in 1..7 {
+            let possible = rng.possible_next();
+            let piece = rng.next_piece();
+
+            assert!(possible.contains(&piece));
+            assert_eq!(rng.bag_mask().count_ones(), 7 - dealt);
+            assert_eq!(rng.bag_mask() & bit(piece), 0);
+        }
+    }
+
+    #[test]
+    fn with_bag_finishes_the_bag_first() {
+        let mask = bit(Piece::T) | bit(Piece::I) | bit(Piece::S);
+        let mut rng = Rng::with_bag(99, mask);
+
+        let rest: Vec<Piece> = (0..3).map(|_| rng.next_piece()).collect();
+        assert_eq!(sorted(rest), vec![Piece::S, Piece::T, Piece::I]);
+
+        let next: Vec<Piece> = (0..7).map(|_| rng.next_piece()).collect();
+        assert_eq!(sorted(next), bag_order.to_vec());
+    }
+
+    #[test]
+    fn same_seed_same_pieces() {
+        let mut a = Rng::with_bag(3, 0b101);
+        let mut b = Rng::with_bag(3, 0b101);
+
+        for _ in 0..20 {
+            assert_eq!(a.next_piece(), b.next_piece());
+        }
+    }
+
+    #[test]
+    fn deal_masks() {
+        assert_eq!(possible_next(0), bag_order.to_vec());
+        assert_eq!(possible_next(bit(Piece::O)), vec![Piece::O]);
+
+        assert_eq!(deal(0, Piece::T), Some(full_bag & !bit(Piece::T)));
+        assert_eq!(deal(bit(Piece::I), Piece::T), None);
+        assert_eq!(deal(bit(Piece::I), Piece::I), Some(0));
+
+        assert_eq!(deal_all(0, &bag_order), Some(0));
+        assert_eq!(deal_all(0, &[Piece::S, Piece::S]), None);
+        // a used up bag starts over
+        assert_eq!(deal_all(bit(Piece::Z), &[Piece::Z, Piece::Z]), Some(full_bag & !bit(Piece::Z)));
+    }
+}
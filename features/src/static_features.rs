@@ -1,9 +1,10 @@
 use rusqlite::fallible_iterator::IteratorExt;
 use tetris::board::Board;
-use tetris::moves::Move;
-use tetris::piece::{Piece,Rotation};
+use tetris::movegen::movegen;
+use tetris::piece::Piece;
 
 use crate::game::{GameState};
+use crate::placement::{clear_lines, is_tspin, place};
 
 pub struct StaticFeatures {
     pub sunbeam_max_height:u32,
@@ -78,8 +79,54 @@ pub fn sunbeam_holes(board: &Board, heights: &[u32; 10], well_x: usize) -> (i16,
     (holes as i16, min_height as i16)
 }
 
+// Sunbeam's donation search. Repeatedly takes the best T-spin slot on the board (TSS, TSD
+// or TST), fills it and clears, up to `depth` times. A slot only counts if movegen can get
+// a T into it with a spin, so closed off cavities that merely have the right shape don't.
+// Counts slots by lines cleared and leaves the board and heights as they are after the
+// donations.
 pub fn sunbeam_donations(board: &mut Board, heights: &mut [u32; 10], depth: usize) -> ([i16; 4], i16) {
-    ([0i16;4], 0i16)
+    let mut t_clears = [0i16; 4];
+    let mut donations = 0i16;
+
+    for _ in 0..depth {
+        let mut best: Option<(u32, i8, Board)> = None;
+
+        for mv in movegen(board, Piece::T) {
+            if !is_tspin(board, &mv) {
+                continue;
+            }
+
+            let mut filled = *board;
+            place(&mut filled, &mv);
+            let lines = clear_lines(&mut filled);
+
+            if lines == 0 {
+                continue;
+            }
+
+            // most lines first, then the lowest slot
+            let better = match &best {
+                None => true,
+                Some((best_lines, best_y, _)) => lines > *best_lines || (lines == *best_lines && mv.y < *best_y),
+            };
+
+            if better {
+                best = Some((lines, mv.y, filled));
+            }
+        }
+
+        let Some((lines, _, filled)) = best else {
+            break;
+        };
+
+        t_clears[(lines as usize).min(3)] += 1;
+        donations += 1;
+
+        *board = filled;
+        *heights = board.heights();
+    }
+
+    (t_clears, donations)
 }
 
 pub fn cc_count_holes(board: &Board, heights: &[u32; 10]) -> i16 {
@@ -148,4 +195,49 @@ pub fn get_static_features(game:&GameState) -> StaticFeatures {
         cc_coveredness:cc_coveredness,
         cc_row_transitions:cc_row_transitions
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // rows 0 and 1 full apart from a T-shaped hole at x 3..5, with an overhang at (3, 2)
+    fn tsd() -> Board {
+        let mut board = Board::new();
+        for x in 0..10 {
+            if x != 4 {
+                board.set(x, 0);
+            }
+            if !(3..=5).contains(&x) {
+                board.set(x, 1);
+            }
+        }
+        board.set(3, 2);
+        board
+    }
+
+    fn donations(mut board: Board) -> ([i16; 4], i16, u32) {
+        let mut heights = board.heights();
+        let (t_clears, n) = sunbeam_donations(&mut board, &mut heights, 2);
+        (t_clears, n, *heights.iter().max().unwrap())
+    }
+
+    #[test]
+    fn flat_board_has_no_donations() {
+        assert_eq!(donations(Board::new()), ([0; 4], 0, 0));
+    }
+
+    #[test]
+    fn open_tsd_is_donated() {
+        // the overhang drops to the floor once both rows clear
+        assert_eq!(donations(tsd()), ([0, 0, 1, 0], 1, 1));
+    }
+
+    #[test]
+    fn sealed_slot_is_not_reachable() {
+        let mut board = tsd();
+        board.set(4, 2);
+        board.set(5, 2);
+
+        assert_eq!(donations(board).1, 0);
+    }
+}
@@ -0,0 +1,289 @@
+use std::fmt;
+
+use crate::feature_extractor::Row;
+use crate::game::State;
+
+/*
+    Value targets for training rows, worked out one game at a time so a
+    label never leaks into a neighbouring game. The game's outcome is
+    1 for P1_WIN, -1 for P2_WIN and `draw` for a draw or a game that stops
+    while still PLAYING. Earlier rows get a value derived from the outcome
+    and how many moves were left, depending on the scheme.
+
+    Window and TimeToDeath only reach back so far from the end. Rows further
+    back have no target and are written as NULL, never as 0, which would read
+    as a drawn outcome. train/data.py only trains on non-NULL, non-zero
+    targets, so those rows are left out there; `features relabel` gives every
+    row a TD(λ) target bootstrapped from a model instead.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    // outcome * factor^moves_left
+    Discount { factor: f32 },
+    // outcome * factor^moves_left like Discount, but only for rows within `n` moves of the end.
+    // This is not an n-step return, which would bootstrap from a model's value n moves on.
+    // Extraction has no model, `features relabel` is where targets bootstrap.
+    Window { n: usize, factor: f32 },
+    // outcome * (1 - moves_left / horizon), none at or past the horizon
+    TimeToDeath { horizon: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target {
+    pub scheme: Scheme,
+    pub draw: f32,
+}
+
+impl Default for Target {
+    // what create_dataset always used
+    fn default() -> Self {
+        Target {
+            scheme: Scheme::Discount { factor: 50.0 / 60.0 },
+            draw: 0.0,
+        }
+    }
+}
+
+impl Scheme {
+    // `discount[:factor]`, `window:n[:factor]` or `ttd:horizon`
+    pub fn parse(s: &str) -> Result<Scheme, String> {
+        let parts: Vec<&str> = s.split(':').collect();
+
+        let number = |i: usize, default: Option<&str>| -> Result<&str, String> {
+            parts.get(i).copied().or(default).ok_or_else(|| format!("{:?} is missing a value", s))
+        };
+
+        let scheme = match parts[0] {
+            "discount" => Scheme::Discount {
+                factor: number(1, Some("0.8333333"))?.parse().map_err(|e| format!("bad factor in {:?}: {}", s, e))?,
+            },
+            "window" => Scheme::Window {
+                n: number(1, None)?.parse().map_err(|e| format!("bad n in {:?}: {}", s, e))?,
+                factor: number(2, Some("1.0"))?.parse().map_err(|e| format!("bad factor in {:?}: {}", s, e))?,
+            },
+            "ttd" => Scheme::TimeToDeath {
+                horizon: number(1, None)?.parse().map_err(|e| format!("bad horizon in {:?}: {}", s, e))?,
+            },
+            other => return Err(format!("unknown target scheme {:?}, expected discount, window or ttd", other)),
+        };
+
+        if let Scheme::TimeToDeath { horizon: 0 } = scheme {
+            return Err("ttd horizon must be at least 1".to_string());
+        }
+
+        Ok(scheme)
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scheme::Discount { factor } => write!(f, "discount:{}", factor),
+            Scheme::Window { n, factor } => write!(f, "window:{}:{}", n, factor),
+            Scheme::TimeToDeath { horizon } => write!(f, "ttd:{}", horizon),
+        }
+    }
+}
+
+impl Target {
+    fn outcome(&self, last: State) -> f32 {
+        match last {
+            State::P1_WIN => 1.0,
+            State::P2_WIN => -1.0,
+            State::DRAW | State::PLAYING => self.draw,
+        }
+    }
+
+    // None when the scheme doesn't reach this far back
+    fn value(&self, outcome: f32, moves_left: usize) -> Option<f32> {
+        match self.scheme {
+            Scheme::Discount { factor } => Some(outcome * factor.powi(moves_left as i32)),
+            Scheme::Window { n, factor } => {
+                (moves_left <= n).then(|| outcome * factor.powi(moves_left as i32))
+            }
+            Scheme::TimeToDeath { horizon } => {
+                (moves_left < horizon).then(|| outcome * (1.0 - moves_left as f32 / horizon as f32))
+            }
+        }
+    }
+
+    // Targets for one game's states, in move order
+    pub fn label_game(&self, states: &[State]) -> Vec<Option<f32>> {
+        let Some(&last) = states.last() else {
+            return Vec::new();
+        };
+
+        let outcome = self.outcome(last);
+
+        (0..states.len())
+            .map(|i| self.value(outcome, states.len() - 1 - i))
+            .collect()
+    }
+
+    // Fills in ground_truth, None where the scheme gives no target. Rows must be sorted by
+    // game_id then move_index, with every game whole.
+    pub fn label_rows(&self, rows: &mut [Row]) {
+        for game in rows.chunk_by_mut(|a, b| a.game_id == b.game_id) {
+            let states: Vec<State> = game.iter().map(|row| row.state).collect();
+
+            for (row, value) in game.iter_mut().zip(self.label_game(&states)) {
+                row.ground_truth = value;
+            }
+        }
+    }
+}
+
+// TD(λ) targets for one game, from the model's predictions at each of its rows, mirroring
+// train/td_lambda.py. Working back from the end, each target is
+//   (1 - λ)(p_i + λ p_i+1 + λ² p_i+2 + ... + R λ^t) / (1 - λ^(t+1))
+// where the last division corrects the weights for the game being only t moves long instead
+// of the infinite series. A win or loss is terminal with reward ±1. Like the Python, a draw
+// has no reward and is bootstrapped like a game that stops while still PLAYING: the last row
+// keeps its own prediction rather than bootstrapping past the end.
+pub fn td_lambda(predictions: &[f32], states: &[State], lambda: f32) -> Vec<f32> {
+    debug_assert_eq!(predictions.len(), states.len());
+
+    let mut targets = vec![0.0; predictions.len()];
+    let mut target = 0.0;
+    // λ^terms so far, the last row of an unfinished game has one term
+    let mut lambda_n = 1.0;
+
+    for i in (0..predictions.len()).rev() {
+        let reward = match states[i] {
+            State::P1_WIN => Some(1.0),
+            State::P2_WIN => Some(-1.0),
+            State::DRAW | State::PLAYING => None,
+        };
+
+        match reward {
+            Some(reward) => {
+                target = reward;
+                lambda_n = lambda;
+            }
+            None => {
+                target = predictions[i] + lambda * target;
+                lambda_n *= lambda;
+            }
+        }
+
+        targets[i] = target * (1.0 - lambda) / (1.0 - lambda_n);
+    }
+
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(moves: usize, last: State) -> Vec<State> {
+        let mut states = vec![State::PLAYING; moves];
+        states.push(last);
+        states
+    }
+
+    fn target(scheme: Scheme) -> Target {
+        Target { scheme, draw: 0.0 }
+    }
+
+    #[test]
+    fn parse_round_trips() {
+        for s in ["discount:0.5", "window:10:0.95", "ttd:60"] {
+            assert_eq!(Scheme::parse(s).unwrap().to_string(), s);
+        }
+        assert!(Scheme::parse("ttd:0").is_err());
+        assert!(Scheme::parse("window").is_err());
+        assert!(Scheme::parse("nstep:10").is_err());
+        assert!(Scheme::parse("lambda").is_err());
+    }
+
+    #[test]
+    fn discount_labels_every_row() {
+        let values = target(Scheme::Discount { factor: 0.5 }).label_game(&game(2, State::P2_WIN));
+        assert_eq!(values, vec![Some(-0.25), Some(-0.5), Some(-1.0)]);
+    }
+
+    #[test]
+    fn window_leaves_rows_past_n_unlabelled() {
+        let values = target(Scheme::Window { n: 1, factor: 0.5 }).label_game(&game(3, State::P1_WIN));
+        assert_eq!(values, vec![None, None, Some(0.5), Some(1.0)]);
+    }
+
+    #[test]
+    fn ttd_leaves_rows_past_the_horizon_unlabelled() {
+        let values = target(Scheme::TimeToDeath { horizon: 2 }).label_game(&game(2, State::P1_WIN));
+        assert_eq!(values, vec![None, Some(0.5), Some(1.0)]);
+    }
+
+    #[test]
+    fn unfinished_games_use_the_draw_value() {
+        let t = Target { scheme: Scheme::Discount { factor: 1.0 }, draw: 0.25 };
+        assert_eq!(t.label_game(&game(1, State::PLAYING)), vec![Some(0.25), Some(0.25)]);
+    }
+
+    // compute_targets_numba from train/td_lambda.py, line for line
+    fn python_td_lambda(predictions: &[f32], states: &[State], lambda: f32) -> Vec<f32> {
+        let reward = |state: State| match state {
+            State::P1_WIN => 1.0,
+            State::P2_WIN => -1.0,
+            _ => 0.0,
+        };
+
+        let mut targets = vec![0.0; predictions.len()];
+        let mut target = 0.0;
+        let mut lambda_n = lambda;
+
+        for i in (0..predictions.len()).rev() {
+            if reward(states[i]) != 0.0 {
+                target = reward(states[i]);
+                lambda_n = lambda;
+            } else {
+                target = predictions[i] + lambda * target;
+                lambda_n *= lambda;
+            }
+            targets[i] = target * (1.0 - lambda) / (1.0 - lambda_n);
+        }
+
+        targets
+    }
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-6)
+    }
+
+    #[test]
+    fn td_lambda_by_hand() {
+        let targets = td_lambda(&[0.2, 0.4, 0.0], &game(2, State::P1_WIN), 0.5);
+
+        // 1, (0.4 + 0.5) * 0.5 / 0.75, (0.2 + 0.45) * 0.5 / 0.875
+        assert!(close(&targets, &[0.65 / 1.75, 0.6, 1.0]), "{:?}", targets);
+    }
+
+    #[test]
+    fn td_lambda_matches_python_on_decided_games() {
+        let predictions = [0.1, -0.3, 0.5, 0.2, 0.9, -0.7, 0.0, 0.4];
+        let mut states = game(3, State::P2_WIN);
+        states.extend(game(3, State::P1_WIN));
+
+        for lambda in [0.0, 0.3, 0.5, 0.9] {
+            // python runs over the whole table, rust a game at a time, decided games agree
+            let python = python_td_lambda(&predictions, &states, lambda);
+            let mut rust = td_lambda(&predictions[..4], &states[..4], lambda);
+            rust.extend(td_lambda(&predictions[4..], &states[4..], lambda));
+
+            assert!(close(&rust, &python), "lambda {}: {:?} vs {:?}", lambda, rust, python);
+        }
+    }
+
+    #[test]
+    fn td_lambda_draws_and_unfinished_games() {
+        // the last row of a drawn or unfinished game keeps its prediction, earlier rows bootstrap from it
+        for last in [State::DRAW, State::PLAYING] {
+            let targets = td_lambda(&[0.3, 0.9], &game(1, last), 0.5);
+
+            // 0.9, (0.3 + 0.45) * 0.5 / 0.75
+            assert!(close(&targets, &[0.5, 0.9]), "{:?}: {:?}", last, targets);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use tetris::board::Board;
+use tetris::moves::Move;
+use tetris::piece::{Piece, Rotation};
+
+use crate::board_codec::max_height;
+use crate::game::{Datum, GameState, State};
+use crate::placement::{Spin, fits, play, spin};
+
+/*
+    Two player game rules on top of placement::play.
+
+    A placement that clears lines sends attack. Attack first cancels the
+    sender's own meter, whatever is left goes into the opponent's meter.
+    A placement that clears nothing lets up to `garbage_cap` rows of the
+    meter land, one hole column per batch. A player tops out when their next
+    piece can't spawn, or when the stack fills a column of the board, past
+    which garbage would push cells off the top.
+*/
+
+// rows of garbage that land on one placement, the rest stays in the meter
+pub const garbage_cap: u8 = 8;
+
+// where pieces enter, facing north, the same as the bots' movegen
+pub const spawn_x: i8 = 4;
+pub const spawn_y: i8 = 20;
+
+// attack by lines cleared, for normal clears, T-spins and T-spin minis
+const clear_attack: [u8; 5] = [0, 0, 1, 2, 4];
+const tspin_attack: [u8; 4] = [0, 2, 4, 6];
+const mini_attack: [u8; 3] = [0, 0, 1];
+
+// bonus by combo count, the first clear of a combo is combo 1
+const combo_attack: [u8; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+const perfect_clear_attack: u8 = 10;
+
+// Attack sent by a clear, given combo and b2b as they are after the clear
+pub fn attack(lines: u32, spin: Spin, combo: u8, b2b: u8, perfect_clear: bool) -> u8 {
+    if lines == 0 {
+        return 0;
+    }
+
+    let lines = lines.min(4) as usize;
+
+    let mut sent = match spin {
+        Spin::Full => tspin_attack[lines.min(3)],
+        Spin::Mini => mini_attack[lines.min(2)],
+        Spin::None => clear_attack[lines],
+    };
+
+    // b2b was already going before this clear
+    if (lines == 4 || spin != Spin::None) && b2b > 1 {
+        sent += 1;
+    }
+
+    sent += combo_attack[(combo as usize).min(combo_attack.len() - 1)];
+
+    if perfect_clear {
+        sent += perfect_clear_attack;
+    }
+
+    sent
+}
+
+// push the stack up by `rows` and fill them in, except for column `hole`
+pub fn add_garbage(board: &mut Board, rows: u8, hole: usize) {
+    if rows == 0 {
+        return;
+    }
+
+    let fill = (1u64 << rows) - 1;
+
+    for (x, col) in board.cols.iter_mut().enumerate() {
+        *col <<= rows;
+        if x != hole {
+            *col |= fill;
+        }
+    }
+}
+
+pub fn topped_out(state: &GameState) -> bool {
+    let spawn = Move { x: spawn_x, y: spawn_y, r: Rotation::North, kind: state.current_piece, tspin: None };
+
+    !fits(&state.board, &spawn) || state.board.heights().iter().any(|&h| h as usize >= max_height)
+}
+
+// Plays `mv` for one player. `attack` is left as what this placement sends to the opponent
+// after cancelling, and `damage_received` as the garbage rows that landed.
+pub fn place(state: &GameState, mv: &Move, next: impl FnMut() -> Piece, mut hole: impl FnMut() -> usize) -> GameState {
+    let spin = spin(&state.board, mv);
+    let (mut ret, lines) = play(state, mv, next);
+
+    let perfect_clear = lines > 0 && ret.board.cols.iter().all(|&col| col == 0);
+    let sent = attack(lines, spin, ret.combo, ret.b2b, perfect_clear);
+
+    let cancelled = sent.min(ret.meter);
+    ret.meter -= cancelled;
+    ret.attack = sent - cancelled;
+    ret.damage_received = 0;
+
+    if lines == 0 && ret.meter > 0 {
+        let rows = ret.meter.min(garbage_cap);
+        add_garbage(&mut ret.board, rows, hole());
+        ret.meter -= rows;
+        ret.damage_received = rows;
+    }
+
+    ret
+}
+
+fn outcome(p1_dead: bool, p2_dead: bool) -> State {
+    match (p1_dead, p2_dead) {
+        (false, false) => State::PLAYING,
+        (true, false) => State::P2_WIN,
+        (false, true) => State::P1_WIN,
+        (true, true) => State::DRAW,
+    }
+}
+
+// Player `p` (0 for P1) places alone, their attack goes straight into the opponent's meter.
+// Used by searches that alternate players.
+pub fn play_turn(datum: &Datum, p: usize, mv: &Move, next: impl FnMut() -> Piece, hole: impl FnMut() -> usize) -> Datum {
+    let mut ret = datum.clone();
+
+    if p == 0 {
+        ret.p1 = place(&datum.p1, mv, next, hole);
+        ret.p2.meter = ret.p2.meter.saturating_add(ret.p1.attack);
+    } else {
+        ret.p2 = place(&datum.p2, mv, next, hole);
+        ret.p1.meter = ret.p1.meter.saturating_add(ret.p2.attack);
+    }
+
+    ret.state = outcome(topped_out(&ret.p1), topped_out(&ret.p2));
+
+    ret
+}
+
+// Both players place at the same time. Garbage already in a meter can land this move,
+// attack sent this move only lands on a later one.
+pub fn step(
+    datum: &Datum,
+    p1_move: &Move,
+    p2_move: &Move,
+    p1_next: impl FnMut() -> Piece,
+    p2_next: impl FnMut() -> Piece,
+    mut hole: impl FnMut() -> usize,
+) -> Datum {
+    let mut ret = datum.clone();
+
+    ret.p1 = place(&datum.p1, p1_move, p1_next, &mut hole);
+    ret.p2 = place(&datum.p2, p2_move, p2_next, &mut hole);
+
+    ret.p1.meter = ret.p1.meter.saturating_add(ret.p2.attack);
+    ret.p2.meter = ret.p2.meter.saturating_add(ret.p1.attack);
+
+    ret.state = outcome(topped_out(&ret.p1), topped_out(&ret.p2));
+    ret.move_index = ret.move_index.saturating_add(1);
+
+    ret
+}
+
+// a player with no legal placement has topped out as well
+pub fn forfeit(datum: &Datum, p1_stuck: bool, p2_stuck: bool) -> Datum {
+    let mut ret = datum.clone();
+
+    let p1_dead = p1_stuck || datum.state == State::P2_WIN || datum.state == State::DRAW;
+    let p2_dead = p2_stuck || datum.state == State::P1_WIN || datum.state == State::DRAW;
+
+    ret.state = outcome(p1_dead, p2_dead);
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(piece: Piece) -> GameState {
+        GameState::new(piece, [Piece::I; 5])
+    }
+
+    #[test]
+    fn tall_stack_away_from_spawn_is_alive() {
+        let mut s = state(Piece::T);
+        for y in 0..30 {
+            s.board.set(0, y);
+        }
+
+        assert!(!topped_out(&s));
+    }
+
+    #[test]
+    fn blocked_spawn_tops_out() {
+        let mut s = state(Piece::T);
+        s.board.set(spawn_x, spawn_y);
+
+        assert!(topped_out(&s));
+        assert!(!topped_out(&state(Piece::T)));
+    }
+
+    #[test]
+    fn full_column_tops_out() {
+        let mut s = state(Piece::T);
+        s.board.cols[9] = u64::MAX;
+
+        assert!(topped_out(&s));
+    }
+
+    #[test]
+    fn attack_table() {
+        assert_eq!(attack(0, Spin::None, 0, 0, false), 0);
+        assert_eq!(attack(1, Spin::None, 1, 0, false), 0);
+        assert_eq!(attack(2, Spin::None, 1, 0, false), 1);
+        assert_eq!(attack(4, Spin::None, 1, 1, false), 4);
+        assert_eq!(attack(2, Spin::Full, 1, 1, false), 4);
+        assert_eq!(attack(3, Spin::Full, 1, 1, false), 6);
+        assert_eq!(attack(1, Spin::Mini, 1, 1, false), 0);
+        assert_eq!(attack(2, Spin::Mini, 1, 1, false), 1);
+    }
+
+    #[test]
+    fn attack_bonuses() {
+        // b2b counts the clear itself, so the bonus starts at 2
+        assert_eq!(attack(4, Spin::None, 1, 2, false), 5);
+        assert_eq!(attack(2, Spin::Full, 1, 3, false), 5);
+        assert_eq!(attack(2, Spin::None, 1, 5, false), 1);
+
+        assert_eq!(attack(1, Spin::None, 3, 0, false), 1);
+        assert_eq!(attack(1, Spin::None, 40, 0, false), 5);
+
+        assert_eq!(attack(4, Spin::None, 1, 1, true), 14);
+    }
+
+    #[test]
+    fn garbage_comes_in_under_the_stack() {
+        let mut board = Board::new();
+        board.set(3, 0);
+
+        add_garbage(&mut board, 2, 3);
+
+        assert_eq!(board.cols[3], 0b100);
+        assert!(board.cols.iter().enumerate().all(|(x, &col)| x == 3 || col == 0b11));
+
+        let before = board;
+        add_garbage(&mut board, 0, 5);
+        assert_eq!(board, before);
+    }
+}
@@ -0,0 +1,596 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Map, Number, Value};
+
+use crate::feature_extractor::{check_input_width, feature_vector};
+use crate::game::Datum;
+use crate::manifest;
+
+/*
+    Native evaluator for models saved by xgboost's `save_model`.
+
+    Both the UBJSON (.ubj) and JSON (.json) formats are read into a serde_json
+    value, then every tree is flattened into one node array. Leaves keep their
+    value in `threshold`, the same way xgboost stores them in `split_conditions`.
+*/
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(std::io::Error),
+    Parse(String),
+    Format(String),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::Io(e) => write!(f, "could not read model: {}", e),
+            ModelError::Parse(e) => write!(f, "could not parse model: {}", e),
+            ModelError::Format(e) => write!(f, "unsupported model: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+impl From<std::io::Error> for ModelError {
+    fn from(e: std::io::Error) -> Self {
+        ModelError::Io(e)
+    }
+}
+
+fn format_error(msg: impl Into<String>) -> ModelError {
+    ModelError::Format(msg.into())
+}
+
+// UBJSON reader, only what xgboost writes plus the rest of the draft 12 markers
+struct Ubjson<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Ubjson<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ModelError> {
+        if self.pos + n > self.bytes.len() {
+            return Err(ModelError::Parse(format!("unexpected end of data at byte {}", self.pos)));
+        }
+        let ret = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(ret)
+    }
+
+    fn marker(&mut self) -> Result<u8, ModelError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn int(&mut self, marker: u8) -> Result<i64, ModelError> {
+        Ok(match marker {
+            b'i' => self.take(1)?[0] as i8 as i64,
+            b'U' => self.take(1)?[0] as i64,
+            b'I' => i16::from_be_bytes(self.take(2)?.try_into().unwrap()) as i64,
+            b'l' => i32::from_be_bytes(self.take(4)?.try_into().unwrap()) as i64,
+            b'L' => i64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            m => return Err(ModelError::Parse(format!("expected an integer, found marker {:?}", m as char))),
+        })
+    }
+
+    fn length(&mut self) -> Result<usize, ModelError> {
+        let marker = self.marker()?;
+        let n = self.int(marker)?;
+        usize::try_from(n).map_err(|_| ModelError::Parse(format!("negative length {}", n)))
+    }
+
+    fn string(&mut self) -> Result<String, ModelError> {
+        let n = self.length()?;
+        let bytes = self.take(n)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| ModelError::Parse(e.to_string()))
+    }
+
+    fn float(v: f64) -> Value {
+        Number::from_f64(v).map_or(Value::Null, Value::Number)
+    }
+
+    fn value(&mut self, marker: u8) -> Result<Value, ModelError> {
+        Ok(match marker {
+            b'Z' => Value::Null,
+            b'T' => Value::Bool(true),
+            b'F' => Value::Bool(false),
+            b'i' | b'U' | b'I' | b'l' | b'L' => Value::Number(self.int(marker)?.into()),
+            b'd' => Self::float(f32::from_be_bytes(self.take(4)?.try_into().unwrap()) as f64),
+            b'D' => Self::float(f64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+            b'C' => Value::String((self.take(1)?[0] as char).to_string()),
+            b'S' | b'H' => Value::String(self.string()?),
+            b'[' => self.array()?,
+            b'{' => self.object()?,
+            m => return Err(ModelError::Parse(format!("unknown marker {:?} at byte {}", m as char, self.pos - 1))),
+        })
+    }
+
+    // optional `$type` and `#count` of an optimized container
+    fn container_header(&mut self) -> Result<(Option<u8>, Option<usize>), ModelError> {
+        let mut kind = None;
+        let mut count = None;
+
+        if self.peek() == Some(b'$') {
+            self.pos += 1;
+            kind = Some(self.marker()?);
+        }
+        if self.peek() == Some(b'#') {
+            self.pos += 1;
+            count = Some(self.length()?);
+        }
+
+        Ok((kind, count))
+    }
+
+    fn array(&mut self) -> Result<Value, ModelError> {
+        let (kind, count) = self.container_header()?;
+        let mut items = Vec::with_capacity(count.unwrap_or(0));
+
+        match count {
+            Some(n) => {
+                for _ in 0..n {
+                    let marker = match kind {
+                        Some(k) => k,
+                        None => self.marker()?,
+                    };
+                    items.push(self.value(marker)?);
+                }
+            }
+            None => loop {
+                match self.marker()? {
+                    b']' => break,
+                    b'N' => continue,
+                    m => items.push(self.value(m)?),
+                }
+            },
+        }
+
+        Ok(Value::Array(items))
+    }
+
+    fn object(&mut self) -> Result<Value, ModelError> {
+        let (kind, count) = self.container_header()?;
+        let mut map = Map::new();
+
+        match count {
+            Some(n) => {
+                for _ in 0..n {
+                    let key = self.string()?;
+                    let marker = match kind {
+                        Some(k) => k,
+                        None => self.marker()?,
+                    };
+                    map.insert(key, self.value(marker)?);
+                }
+            }
+            None => loop {
+                match self.peek() {
+                    Some(b'}') => {
+                        self.pos += 1;
+                        break;
+                    }
+                    Some(b'N') => {
+                        self.pos += 1;
+                        continue;
+                    }
+                    _ => {
+                        let key = self.string()?;
+                        let marker = self.marker()?;
+                        map.insert(key, self.value(marker)?);
+                    }
+                }
+            },
+        }
+
+        Ok(Value::Object(map))
+    }
+}
+
+fn parse_ubjson(bytes: &[u8]) -> Result<Value, ModelError> {
+    let mut reader = Ubjson { bytes, pos: 0 };
+    let marker = reader.marker()?;
+    reader.value(marker)
+}
+
+fn field<'a>(value: &'a Value, path: &[&str]) -> Result<&'a Value, ModelError> {
+    let mut current = value;
+    for key in path {
+        current = current.get(key).ok_or_else(|| format_error(format!("missing field {}", path.join("."))))?;
+    }
+    Ok(current)
+}
+
+fn f32_array(tree: &Value, key: &str) -> Result<Vec<f32>, ModelError> {
+    field(tree, &[key])?.as_array()
+        .ok_or_else(|| format_error(format!("{} is not an array", key)))?
+        .iter()
+        .map(|v| v.as_f64().map(|f| f as f32).ok_or_else(|| format_error(format!("{} holds a non-number", key))))
+        .collect()
+}
+
+fn i64_array(tree: &Value, key: &str) -> Result<Vec<i64>, ModelError> {
+    field(tree, &[key])?.as_array()
+        .ok_or_else(|| format_error(format!("{} is not an array", key)))?
+        .iter()
+        .map(|v| v.as_i64().ok_or_else(|| format_error(format!("{} holds a non-integer", key))))
+        .collect()
+}
+
+// xgboost stores numeric parameters as strings, base_score as "[-1.8E-3]" since 3.0
+fn param_f32(value: &Value) -> Result<f32, ModelError> {
+    let text = value.as_str().ok_or_else(|| format_error("parameter is not a string"))?;
+    text.trim_matches(|c| c == '[' || c == ']')
+        .parse()
+        .map_err(|_| format_error(format!("bad numeric parameter {:?}", text)))
+}
+
+fn param_usize(value: &Value) -> Result<usize, ModelError> {
+    let text = value.as_str().ok_or_else(|| format_error("parameter is not a string"))?;
+    text.parse().map_err(|_| format_error(format!("bad integer parameter {:?}", text)))
+}
+
+const leaf: u32 = u32::MAX;
+
+// Models saved with feature names are matched to the extractor's columns by name, so they can
+// use any subset of them. Models without names have to take every column, in order.
+fn input_positions(num_feature: usize, feature_names: &[String]) -> Result<Vec<usize>, String> {
+    if feature_names.is_empty() {
+        check_input_width(num_feature).map_err(|e| e.to_string())?;
+        return Ok((0..num_feature).collect());
+    }
+
+    if feature_names.len() != num_feature {
+        return Err(format!("model has {} feature names for {} features", feature_names.len(), num_feature));
+    }
+
+    manifest::column_positions(feature_names)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    feature: u32,
+    threshold: f32,
+    left: u32,
+    right: u32,
+    default_left: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Link {
+    Identity,
+    Logistic,
+}
+
+pub struct TreeModel {
+    nodes: Vec<Node>,
+    roots: Vec<u32>,
+    base_margin: f32,
+    link: Link,
+    num_feature: usize,
+    pub feature_names: Vec<String>,
+    // positions in feature_vector() of the model's inputs, see inputs()
+    inputs: Result<Vec<usize>, String>,
+}
+
+impl TreeModel {
+    // .json files are read as JSON, anything else as UBJSON
+    pub fn load(path: impl AsRef<Path>) -> Result<TreeModel, ModelError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+
+        let is_json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+
+        let value = if is_json {
+            serde_json::from_slice(&bytes).map_err(|e| ModelError::Parse(e.to_string()))?
+        } else {
+            parse_ubjson(&bytes)?
+        };
+
+        Self::from_value(&value)
+    }
+
+    pub fn from_value(model: &Value) -> Result<TreeModel, ModelError> {
+        let learner = field(model, &["learner"])?;
+
+        let booster = field(learner, &["gradient_booster", "name"])?.as_str().unwrap_or_default();
+        if booster != "gbtree" {
+            return Err(format_error(format!("booster {:?}, only gbtree is supported", booster)));
+        }
+
+        let params = field(learner, &["learner_model_param"])?;
+        let num_feature = param_usize(field(params, &["num_feature"])?)?;
+        let num_class = param_usize(field(params, &["num_class"])?)?;
+        if num_class > 1 {
+            return Err(format_error("multi-class models are not supported"));
+        }
+        if let Some(num_target) = params.get("num_target") {
+            if param_usize(num_target)? > 1 {
+                return Err(format_error("multi-target models are not supported"));
+            }
+        }
+
+        let base_score = param_f32(field(params, &["base_score"])?)?;
+
+        let objective = field(learner, &["objective", "name"])?.as_str().unwrap_or_default();
+        let link = match objective {
+            "reg:squarederror" | "reg:squaredlogerror" | "reg:pseudohubererror" | "reg:absoluteerror" => Link::Identity,
+            "reg:logistic" | "binary:logistic" => Link::Logistic,
+            other => return Err(format_error(format!("objective {:?}", other))),
+        };
+
+        let base_margin = match link {
+            Link::Identity => base_score,
+            Link::Logistic => (base_score / (1.0 - base_score)).ln(),
+        };
+
+        let feature_names = learner.get("feature_names")
+            .and_then(|v| v.as_array())
+            .map(|names| names.iter().filter_map(|n| n.as_str().map(str::to_string)).collect())
+            .unwrap_or_default();
+
+        let trees = field(learner, &["gradient_booster", "model", "trees"])?
+            .as_array()
+            .ok_or_else(|| format_error("trees is not an array"))?;
+
+        let mut nodes = Vec::new();
+        let mut roots = Vec::with_capacity(trees.len());
+
+        for tree in trees {
+            let offset = nodes.len() as u32;
+
+            let left = i64_array(tree, "left_children")?;
+            let right = i64_array(tree, "right_children")?;
+            let split_indices = i64_array(tree, "split_indices")?;
+            let split_conditions = f32_array(tree, "split_conditions")?;
+            let default_left = i64_array(tree, "default_left")?;
+
+            if let Some(split_type) = tree.get("split_type").and_then(|v| v.as_array()) {
+                if split_type.iter().any(|t| t.as_i64() != Some(0)) {
+                    return Err(format_error("categorical splits are not supported"));
+                }
+            }
+
+            let n = left.len();
+            if [right.len(), split_indices.len(), split_conditions.len(), default_left.len()].iter().any(|&len| len != n) {
+                return Err(format_error("tree arrays have different lengths"));
+            }
+
+            for i in 0..n {
+                if left[i] == -1 {
+                    nodes.push(Node {
+                        feature: leaf,
+                        threshold: split_conditions[i],
+                        left: 0,
+                        right: 0,
+                        default_left: false,
+                    });
+                    continue;
+                }
+
+                let in_tree = |child: i64| child >= 0 && (child as usize) < n;
+                if !in_tree(left[i]) || !in_tree(right[i]) {
+                    return Err(format_error("child index out of range"));
+                }
+                if split_indices[i] < 0 || split_indices[i] as usize >= num_feature {
+                    return Err(format_error(format!("split on feature {} of {}", split_indices[i], num_feature)));
+                }
+
+                nodes.push(Node {
+                    feature: split_indices[i] as u32,
+                    threshold: split_conditions[i],
+                    left: offset + left[i] as u32,
+                    right: offset + right[i] as u32,
+                    default_left: default_left[i] != 0,
+                });
+            }
+
+            roots.push(offset);
+        }
+
+        let inputs = input_positions(num_feature, &feature_names);
+
+        Ok(TreeModel { nodes, roots, base_margin, link, num_feature, feature_names, inputs })
+    }
+
+    pub fn num_feature(&self) -> usize {
+        self.num_feature
+    }
+
+    pub fn num_trees(&self) -> usize {
+        self.roots.len()
+    }
+
+    fn leaf(&self, root: u32, row: &[f32]) -> f32 {
+        let mut node = &self.nodes[root as usize];
+
+        while node.feature != leaf {
+            let value = row[node.feature as usize];
+
+            let next = if value.is_nan() {
+                if node.default_left { node.left } else { node.right }
+            } else if value < node.threshold {
+                node.left
+            } else {
+                node.right
+            };
+
+            node = &self.nodes[next as usize];
+        }
+
+        node.threshold
+    }
+
+    // `row` holds num_feature() values in training column order
+    pub fn predict(&self, row: &[f32]) -> f32 {
+        assert_eq!(row.len(), self.num_feature, "row has {} features, model expects {}", row.len(), self.num_feature);
+
+        let margin = self.roots.iter()
+            .fold(self.base_margin, |acc, &root| acc + self.leaf(root, row));
+
+        self.apply_link(margin)
+    }
+
+    fn apply_link(&self, margin: f32) -> f32 {
+        match self.link {
+            Link::Identity => margin,
+            Link::Logistic => 1.0 / (1.0 + (-margin).exp()),
+        }
+    }
+
+    // `rows` holds rows of num_feature() values back to back, one prediction per row. Trees
+    // go in the outer loop so each tree's nodes stay in cache for the whole batch. Every row
+    // adds its trees up in the same order as predict(), so the results are the same.
+    pub fn predict_batch(&self, rows: &[f32]) -> Vec<f32> {
+        let width = self.num_feature.max(1);
+        assert_eq!(rows.len() % width, 0, "{} values is not a whole number of {} feature rows", rows.len(), width);
+
+        let mut margins = vec![self.base_margin; rows.len() / width];
+
+        for &root in &self.roots {
+            for (margin, row) in margins.iter_mut().zip(rows.chunks_exact(width)) {
+                *margin += self.leaf(root, row);
+            }
+        }
+
+        margins.into_iter().map(|margin| self.apply_link(margin)).collect()
+    }
+
+    // Where the model's inputs are in feature_vector(), or why it can't be fed from it.
+    // Check this once after loading a model meant for predict_datum.
+    pub fn inputs(&self) -> Result<&[usize], String> {
+        self.inputs.as_deref().map_err(String::clone)
+    }
+
+    // `full` is a feature_vector(), only the model's own columns are used
+    pub fn predict_selected(&self, full: &[f32]) -> f32 {
+        let inputs = self.inputs().expect("model inputs do not match the extractor");
+        let row: Vec<f32> = inputs.iter().map(|&i| full[i]).collect();
+
+        self.predict(&row)
+    }
+
+    // `full` holds feature_vector()s of `width` values back to back
+    pub fn predict_selected_batch(&self, full: &[f32], width: usize) -> Vec<f32> {
+        let inputs = self.inputs().expect("model inputs do not match the extractor");
+        let rows: Vec<f32> = full.chunks_exact(width)
+            .flat_map(|row| inputs.iter().map(|&i| row[i]))
+            .collect();
+
+        self.predict_batch(&rows)
+    }
+
+    pub fn predict_datum(&self, datum: &Datum) -> f32 {
+        self.predict_selected(&feature_vector(datum))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tetris::piece::Piece;
+
+    use super::*;
+    use crate::game::{GameState, State};
+
+    // trained and predicted by xgboost in train/make_xgb_fixture.py
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join("xgboost").join(name)
+    }
+
+    fn load(name: &str) -> TreeModel {
+        TreeModel::load(fixture(name)).unwrap_or_else(|e| panic!("{}: {}, run train/make_xgb_fixture.py", name, e))
+    }
+
+    fn expected() -> (Vec<Vec<f32>>, Value) {
+        let bytes = fs::read(fixture("predictions.json")).unwrap_or_else(|e| panic!("predictions.json: {}, run train/make_xgb_fixture.py", e));
+        let predictions: Value = serde_json::from_slice(&bytes).unwrap();
+        let rows = predictions["rows"].as_array().unwrap().iter()
+            .map(|row| row.as_array().unwrap().iter().map(|x| x.as_f64().map_or(f32::NAN, |x| x as f32)).collect())
+            .collect();
+
+        (rows, predictions)
+    }
+
+    fn check(file: &str, name: &str) {
+        let (rows, predictions) = expected();
+
+        // parity only means something against xgboost itself
+        let source = predictions[format!("{}_source", name)].as_str().unwrap_or_default();
+        assert!(source.starts_with("xgboost "), "{} predictions come from {:?}, rerun train/make_xgb_fixture.py with xgboost", name, source);
+
+        // the missing values have to be exercised too
+        assert!(rows.iter().any(|row| row.iter().any(|x| x.is_nan())));
+
+        let model = load(file);
+        assert_eq!(model.num_feature(), rows[0].len());
+
+        for (row, want) in rows.iter().zip(predictions[name].as_array().unwrap()) {
+            let want = want.as_f64().unwrap() as f32;
+            let got = model.predict(row);
+            assert!((got - want).abs() < 1e-5, "{}: {:?} predicted {}, xgboost {}", file, row, got, want);
+        }
+
+        let flat: Vec<f32> = rows.concat();
+        let single: Vec<f32> = rows.iter().map(|row| model.predict(row)).collect();
+        assert_eq!(model.predict_batch(&flat), single);
+    }
+
+    #[test]
+    fn json_matches_xgboost() {
+        check("regression.json", "regression");
+        check("logistic.json", "logistic");
+    }
+
+    #[test]
+    fn ubjson_matches_xgboost() {
+        check("regression.ubj", "regression");
+        check("logistic.ubj", "logistic");
+    }
+
+    #[test]
+    fn base_score_forms() {
+        assert_eq!(param_f32(&Value::from("[-1.8339268E-3]")).unwrap(), -1.8339268e-3);
+        assert_eq!(param_f32(&Value::from("5E-1")).unwrap(), 0.5);
+        assert!(param_f32(&Value::from("[]")).is_err());
+        assert!(param_f32(&Value::from(0.5)).is_err());
+    }
+
+    #[test]
+    fn unnamed_model_must_take_every_column() {
+        assert!(load("regression.json").inputs().is_err());
+    }
+
+    #[test]
+    fn shipped_model_loads() {
+        let model = TreeModel::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("../models/model.ubj")).unwrap();
+        assert_eq!(model.inputs().unwrap().len(), model.num_feature());
+    }
+
+    #[test]
+    fn batches_predict_like_single_rows() {
+        let model = TreeModel::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("../models/model.ubj")).unwrap();
+
+        let mut data = Vec::new();
+        for (current, height) in [(Piece::T, 0), (Piece::I, 3), (Piece::S, 7)] {
+            let mut p1 = GameState::new(current, [Piece::O, Piece::L, Piece::J, Piece::Z, Piece::I]);
+            for y in 0..height {
+                for x in 0..9 {
+                    p1.board.set((x + y) % 10, y);
+                }
+            }
+            let p2 = GameState::new(Piece::L, [Piece::T; 5]);
+            data.push(Datum { p1, p2, state: State::PLAYING, game_id: 0, move_index: 0 });
+        }
+
+        let full: Vec<f32> = data.iter().flat_map(feature_vector).collect();
+        let single: Vec<f32> = data.iter().map(|datum| model.predict_datum(datum)).collect();
+
+        assert_eq!(model.predict_selected_batch(&full, full.len() / data.len()), single);
+    }
+}
@@ -0,0 +1,169 @@
+use std::env;
+
+use hachi::selfplay::{make_bot, play_game};
+
+use features::game::State;
+
+/*
+    Plays two bot configurations against each other and reports how much
+    stronger the first one is.
+
+    arena <bot a> <bot b> [games] [seed] [elo0] [elo1]
+
+    Games come in pairs with the same seed and the sides swapped, so both bots
+    play the same pieces from both seats. Results are counted for bot A. After
+    every pair a sequential probability ratio test checks elo0 against elo1
+    (defaults 0 and 10) at alpha = beta = 0.05, and the match stops early once
+    it has a verdict. See hachi::selfplay for how bots are given.
+*/
+
+const alpha: f64 = 0.05;
+const beta: f64 = 0.05;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Results {
+    wins: usize,
+    draws: usize,
+    losses: usize,
+}
+
+impl Results {
+    fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    fn score(&self) -> f64 {
+        (self.wins as f64 + 0.5 * self.draws as f64) / self.games() as f64
+    }
+
+    // per game variance of the score
+    fn variance(&self) -> f64 {
+        let n = self.games() as f64;
+        let s = self.score();
+
+        (self.wins as f64 * (1.0 - s).powi(2) + self.draws as f64 * (0.5 - s).powi(2) + self.losses as f64 * s.powi(2)) / n
+    }
+
+    // elo difference with a 95% confidence interval
+    fn elo(&self) -> (f64, f64, f64) {
+        let margin = 1.96 * (self.variance() / self.games() as f64).sqrt();
+        let s = self.score();
+
+        (elo_from_score(s), elo_from_score(s - margin), elo_from_score(s + margin))
+    }
+
+    // log likelihood ratio of elo1 against elo0, normal approximation of the trinomial
+    fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.variance();
+        if variance <= 0.0 {
+            return 0.0;
+        }
+
+        let (s0, s1) = (score_from_elo(elo0), score_from_elo(elo1));
+
+        self.games() as f64 * (s1 - s0) * (2.0 * self.score() - s0 - s1) / (2.0 * variance)
+    }
+}
+
+fn elo_from_score(score: f64) -> f64 {
+    let score = score.clamp(1e-6, 1.0 - 1e-6);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    H0,
+    H1,
+    Continue,
+}
+
+fn sprt(results: &Results, elo0: f64, elo1: f64) -> (f64, Verdict) {
+    let llr = results.llr(elo0, elo1);
+    let lower = (beta / (1.0 - alpha)).ln();
+    let upper = ((1.0 - beta) / alpha).ln();
+
+    let verdict = if llr >= upper {
+        Verdict::H1
+    } else if llr <= lower {
+        Verdict::H0
+    } else {
+        Verdict::Continue
+    };
+
+    (llr, verdict)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+
+    let (Some(a), Some(b)) = (args.get(1), args.get(2)) else {
+        anyhow::bail!("usage: arena <bot a> <bot b> [games] [seed] [elo0] [elo1]");
+    };
+    let games: u16 = args.get(3).map_or(Ok(200), |s| s.parse())?;
+    let seed: u32 = args.get(4).map_or(Ok(0), |s| s.parse())?;
+    let elo0: f64 = args.get(5).map_or(Ok(0.0), |s| s.parse())?;
+    let elo1: f64 = args.get(6).map_or(Ok(10.0), |s| s.parse())?;
+
+    if games == 0 {
+        anyhow::bail!("games has to be at least 1");
+    }
+
+    // bot A sits in the first seat unless the pair is swapped
+    let mut bots = [make_bot(a, seed)?, make_bot(b, seed.wrapping_add(1))?];
+
+    let mut results = Results::default();
+    let mut verdict = Verdict::Continue;
+    let mut llr = 0.0;
+
+    for pair in 0..games.div_ceil(2) {
+        let game_seed = seed.wrapping_add(pair as u32);
+
+        for swapped in [false, true] {
+            let game_id = pair * 2 + swapped as u16;
+            if game_id >= games {
+                break;
+            }
+
+            if swapped {
+                bots.swap(0, 1);
+            }
+            let result = play_game(&mut bots, game_id, game_seed, |_| Ok(()));
+            if swapped {
+                bots.swap(0, 1);
+            }
+            let result = result?;
+
+            match (result, swapped) {
+                (State::P1_WIN, false) | (State::P2_WIN, true) => results.wins += 1,
+                (State::P2_WIN, false) | (State::P1_WIN, true) => results.losses += 1,
+                _ => results.draws += 1,
+            }
+        }
+
+        (llr, verdict) = sprt(&results, elo0, elo1);
+
+        println!("{} games: +{} ={} -{}, llr {:.2}", results.games(), results.wins, results.draws, results.losses, llr);
+
+        if verdict != Verdict::Continue {
+            break;
+        }
+    }
+
+    let (elo, low, high) = results.elo();
+
+    println!();
+    println!("{} vs {}", a, b);
+    println!("W/D/L: {}/{}/{} over {} games, score {:.3}", results.wins, results.draws, results.losses, results.games(), results.score());
+    println!("elo: {:+.1} [{:+.1}, {:+.1}] (95%)", elo, low, high);
+    println!("sprt elo0={} elo1={}: llr {:.2}, {}", elo0, elo1, llr, match verdict {
+        Verdict::H1 => "H1 accepted, A is stronger",
+        Verdict::H0 => "H0 accepted, A is not stronger",
+        Verdict::Continue => "inconclusive",
+    });
+
+    Ok(())
+}
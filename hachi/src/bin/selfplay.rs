@@ -0,0 +1,147 @@
+use std::env;
+
+use rusqlite::{Connection, params};
+
+use hachi::selfplay::{make_bot, play_game};
+
+use features::board_codec;
+use features::game::{Datum, State};
+
+use tetris::piece::{Piece, Rotation};
+
+/*
+    Plays bots against each other and writes every position into the `Data`
+    table that the feature extractor reads, with the columns documented in
+    features/src/main.rs. Rows are the position after each move, with the
+    placement that led to it, starting from the empty boards at move_index 0.
+
+    selfplay <database> [games] [p1 bot] [p2 bot] [seed]
+
+    See hachi::selfplay for how bots are given.
+*/
+
+const create_table: &str = "CREATE TABLE IF NOT EXISTS Data (
+    game_id INTEGER NOT NULL,
+    move_index INTEGER NOT NULL,
+    state TEXT NOT NULL,
+    p1_board BLOB NOT NULL, p1_current_piece TEXT NOT NULL, p1_move_piece_type TEXT NOT NULL,
+    p1_move_piece_rot INTEGER NOT NULL, p1_move_piece_x INTEGER NOT NULL, p1_move_piece_y INTEGER NOT NULL,
+    p1_meter INTEGER NOT NULL, p1_attack INTEGER NOT NULL, p1_damage_received INTEGER NOT NULL,
+    p1_spun INTEGER NOT NULL,
+    p1_queue_0 TEXT NOT NULL, p1_queue_1 TEXT NOT NULL, p1_queue_2 TEXT NOT NULL, p1_queue_3 TEXT NOT NULL, p1_queue_4 TEXT NOT NULL,
+    p1_hold TEXT NOT NULL,
+    p2_board BLOB NOT NULL, p2_current_piece TEXT NOT NULL, p2_move_piece_type TEXT NOT NULL,
+    p2_move_piece_rot INTEGER NOT NULL, p2_move_piece_x INTEGER NOT NULL, p2_move_piece_y INTEGER NOT NULL,
+    p2_meter INTEGER NOT NULL, p2_attack INTEGER NOT NULL, p2_damage_received INTEGER NOT NULL,
+    p2_spun INTEGER NOT NULL,
+    p2_queue_0 TEXT NOT NULL, p2_queue_1 TEXT NOT NULL, p2_queue_2 TEXT NOT NULL, p2_queue_3 TEXT NOT NULL, p2_queue_4 TEXT NOT NULL,
+    p2_hold TEXT NOT NULL,
+    p1_combo INTEGER NOT NULL, p1_b2b INTEGER NOT NULL,
+    p2_combo INTEGER NOT NULL, p2_b2b INTEGER NOT NULL
+)";
+
+const insert_row: &str = "INSERT INTO Data (
+    game_id, move_index, state,
+    p1_board, p1_current_piece, p1_move_piece_type, p1_move_piece_rot, p1_move_piece_x, p1_move_piece_y,
+    p1_meter, p1_attack, p1_damage_received, p1_spun,
+    p1_queue_0, p1_queue_1, p1_queue_2, p1_queue_3, p1_queue_4, p1_hold,
+    p2_board, p2_current_piece, p2_move_piece_type, p2_move_piece_rot, p2_move_piece_x, p2_move_piece_y,
+    p2_meter, p2_attack, p2_damage_received, p2_spun,
+    p2_queue_0, p2_queue_1, p2_queue_2, p2_queue_3, p2_queue_4, p2_hold,
+    p1_combo, p1_b2b, p2_combo, p2_b2b
+) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+fn piece_name(piece: Option<Piece>) -> &'static str {
+    match piece {
+        Some(Piece::I) => "I",
+        Some(Piece::J) => "J",
+        Some(Piece::L) => "L",
+        Some(Piece::O) => "O",
+        Some(Piece::S) => "S",
+        Some(Piece::T) => "T",
+        Some(Piece::Z) => "Z",
+        None => " ",
+    }
+}
+
+fn rotation_index(rotation: Rotation) -> i32 {
+    match rotation {
+        Rotation::North => 0,
+        Rotation::East => 1,
+        Rotation::South => 2,
+        Rotation::West => 3,
+    }
+}
+
+fn state_name(state: State) -> &'static str {
+    match state {
+        State::PLAYING => "PLAYING",
+        State::P1_WIN => "P1_WIN",
+        State::P2_WIN => "P2_WIN",
+        State::DRAW => "DRAW",
+    }
+}
+
+fn insert(conn: &Connection, datum: &Datum) -> rusqlite::Result<()> {
+    let (p1, p2) = (&datum.p1, &datum.p2);
+
+    conn.execute(insert_row, params![
+        datum.game_id, datum.move_index, state_name(datum.state),
+        board_codec::encode(&p1.board), piece_name(Some(p1.current_piece)), piece_name(p1.placement.move_type),
+        rotation_index(p1.placement.rotation), p1.placement.x, p1.placement.y,
+        p1.meter, p1.attack, p1.damage_received, p1.spun as i32,
+        piece_name(Some(p1.queue[0])), piece_name(Some(p1.queue[1])), piece_name(Some(p1.queue[2])),
+        piece_name(Some(p1.queue[3])), piece_name(Some(p1.queue[4])), piece_name(p1.hold),
+        board_codec::encode(&p2.board), piece_name(Some(p2.current_piece)), piece_name(p2.placement.move_type),
+        rotation_index(p2.placement.rotation), p2.placement.x, p2.placement.y,
+        p2.meter, p2.attack, p2.damage_received, p2.spun as i32,
+        piece_name(Some(p2.queue[0])), piece_name(Some(p2.queue[1])), piece_name(Some(p2.queue[2])),
+        piece_name(Some(p2.queue[3])), piece_name(Some(p2.queue[4])), piece_name(p2.hold),
+        p1.combo, p1.b2b, p2.combo, p2.b2b,
+    ])?;
+
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+
+    let Some(db_path) = args.get(1) else {
+        anyhow::bail!("usage: selfplay <database> [games] [p1 bot] [p2 bot] [seed]");
+    };
+    let games: u16 = args.get(2).map_or(Ok(100), |s| s.parse())?;
+    let p1_name = args.get(3).map_or("handcrafted", String::as_str);
+    let p2_name = args.get(4).map_or("downstacker", String::as_str);
+    let seed: u32 = args.get(5).map_or(Ok(0), |s| s.parse())?;
+
+    let conn = Connection::open(db_path)?;
+    conn.execute(create_table, [])?;
+
+    // carry on after whatever games are already in there, game ids have to fit a u16
+    let next_id: i64 = conn.query_row("SELECT COALESCE(MAX(game_id) + 1, 0) FROM Data", [], |row| row.get(0))?;
+    let first_id = u16::try_from(next_id).ok()
+        .filter(|id| id.checked_add(games.saturating_sub(1)).is_some())
+        .ok_or_else(|| anyhow::anyhow!("{} has games up to id {}, {} more would go past {}", db_path, next_id - 1, games, u16::MAX))?;
+
+    let mut bots = [make_bot(p1_name, seed)?, make_bot(p2_name, seed.wrapping_add(1))?];
+    let mut results = [0usize; 3];
+
+    for i in 0..games {
+        let game_id = first_id + i;
+        let tx = conn.unchecked_transaction()?;
+        let result = play_game(&mut bots, game_id, seed.wrapping_add(game_id as u32), |datum| Ok(insert(&tx, datum)?))?;
+        tx.commit()?;
+
+        match result {
+            State::P1_WIN => results[0] += 1,
+            State::P2_WIN => results[1] += 1,
+            _ => results[2] += 1,
+        }
+
+        println!("game {}: {}", game_id, state_name(result));
+    }
+
+    println!("{} {} - {} {} ({} draws)", p1_name, results[0], results[1], p2_name, results[2]);
+
+    Ok(())
+}
@@ -1,11 +1,14 @@
-use features::game::{GameState};
-use features::feature_extractor::{Features, extract_features};
+use features::game::{Datum, GameState, State};
+use features::placement::{play, hold_piece};
+use features::versus;
 
-use tetris::board::Board;
 use tetris::moves::Move;
-use tetris::piece::{Piece, Rotation};
 use tetris::movegen::{movegen};
-use tetris::state::{State};
+
+use std::time::{Duration, Instant};
+
+use crate::eval::Evaluator;
+use crate::tt::{self, Bound, Entry, TranspositionTable, TtStats};
 
 /*
     Nested search combining minimax with beam search.
@@ -37,16 +40,557 @@ use tetris::state::{State};
 
 */
 
-pub fn beam_search(state: &State, depth: i32, width: usize, max_moves: usize) -> Vec<Move> {
+#[derive(Debug, Clone, Copy)]
+pub struct SearchParams {
+    pub depth: i32,
+    pub root_width: usize,
+    pub beam_depth: i32,
+    pub beam_width: usize,
+    // leaves per evaluator call
+    pub batch_size: usize,
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        SearchParams {
+            depth: 5,
+            root_width: 60,
+            beam_depth: 3,
+            beam_width: 100,
+            batch_size: 256,
+        }
+    }
+}
+
+struct BeamNode {
+    state: GameState,
+    // real pieces left in current + queue, everything past that is filler
+    known: usize,
+    root: usize,
+    score: f32,
+}
+
+// every placement of the current piece and of the hold piece, with the number of known pieces left afterwards
+fn expand(state: &GameState, known: usize) -> Vec<(Move, GameState, usize)> {
+    let mut children = Vec::new();
+
+    if known == 0 {
+        return children;
+    }
+
+    // pieces past the visible queue are unknown, repeat the last one as filler
+    let filler = state.queue[4];
+
+    for mv in movegen(&state.board, state.current_piece) {
+        let (child, _) = play(state, &mv, || filler);
+        children.push((mv, child, known - 1));
+    }
+
+    let held = hold_piece(state);
+    let cost = if state.hold.is_some() { 1 } else { 2 };
+
+    if held != state.current_piece && known >= cost {
+        for mv in movegen(&state.board, held) {
+            let (child, _) = play(state, &mv, || filler);
+            children.push((mv, child, known - cost));
+        }
+    }
+
+    children
+}
+
+// fills in node scores, calling `eval` once per `batch_size` nodes
+fn score_nodes(nodes: &mut [BeamNode], batch_size: usize, eval: &mut impl FnMut(&[&GameState]) -> anyhow::Result<Vec<f32>>) -> anyhow::Result<()> {
+    for chunk in nodes.chunks_mut(batch_size.max(1)) {
+        let states: Vec<&GameState> = chunk.iter().map(|node| &node.state).collect();
+        let scores = eval(&states)?;
+
+        for (node, score) in chunk.iter_mut().zip(scores) {
+            node.score = score;
+        }
+    }
 
+    Ok(())
 }
 
-pub fn minimax_search(gamestate: &GameState, depth: i32) -> Move {
-    Move (
-        x: 0,
-        y: 0,
-        r: Rotation::North,
-        kind: Piece::O,
-        tspin: None,
-    )
-}
\ No newline at end of file
+// Beam search over one player's placements. Each layer is scored by `eval` in batches, higher is better.
+// Returns up to `max_moves` root moves, best first, ranked by the deepest layer they reached
+// and then by their best leaf in that layer. Errors from `eval` end the search.
+pub fn beam_search(state: &GameState, depth: i32, width: usize, max_moves: usize, batch_size: usize, eval: &mut impl FnMut(&[&GameState]) -> anyhow::Result<Vec<f32>>) -> anyhow::Result<Vec<Move>> {
+    beam(state, 1 + state.queue.len(), depth, width, max_moves, batch_size, eval)
+}
+
+fn beam(state: &GameState, known: usize, depth: i32, width: usize, max_moves: usize, batch_size: usize, eval: &mut impl FnMut(&[&GameState]) -> anyhow::Result<Vec<f32>>) -> anyhow::Result<Vec<Move>> {
+    let mut roots: Vec<Move> = Vec::new();
+    let mut beam: Vec<BeamNode> = Vec::new();
+
+    for (mv, child, known) in expand(state, known) {
+        beam.push(BeamNode { state: child, known, root: roots.len(), score: 0.0 });
+        roots.push(mv);
+    }
+
+    score_nodes(&mut beam, batch_size, eval)?;
+
+    // (layer, best score) per root
+    let mut best: Vec<(i32, f32)> = beam.iter().map(|node| (0, node.score)).collect();
+
+    for layer in 1..depth.max(1) {
+        if beam.len() > width {
+            beam.sort_unstable_by(|a, b| b.score.total_cmp(&a.score));
+            beam.truncate(width);
+        }
+
+        let mut next = Vec::new();
+
+        for node in &beam {
+            for (_, child, known) in expand(&node.state, node.known) {
+                next.push(BeamNode { state: child, known, root: node.root, score: 0.0 });
+            }
+        }
+
+        if next.is_empty() {
+            break;
+        }
+
+        score_nodes(&mut next, batch_size, eval)?;
+
+        for node in &next {
+            let entry = &mut best[node.root];
+            if entry.0 < layer || node.score > entry.1 {
+                *entry = (layer, node.score);
+            }
+        }
+
+        beam = next;
+    }
+
+    let mut ranked: Vec<usize> = (0..roots.len()).collect();
+    ranked.sort_by(|&a, &b| {
+        best[b].0.cmp(&best[a].0).then(best[b].1.total_cmp(&best[a].1))
+    });
+
+    Ok(ranked.into_iter()
+        .take(max_moves)
+        .map(|i| roots[i])
+        .collect())
+}
+
+// State shared across one or more searches: the transposition table, node count and deadline
+pub struct Search {
+    pub tt: TranspositionTable,
+    pub nodes: u64,
+    deadline: Option<Instant>,
+    aborted: bool,
+}
+
+impl Search {
+    pub fn new(tt_size: usize) -> Self {
+        Search {
+            tt: TranspositionTable::new(tt_size),
+            nodes: 0,
+            deadline: None,
+            aborted: false,
+        }
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        if !self.aborted && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.aborted = true;
+        }
+        self.aborted
+    }
+}
+
+pub struct SearchResult {
+    pub best: Option<Move>,
+    // root moves, best first
+    pub moves: Vec<Move>,
+    pub depth: i32,
+    pub beam_depth: i32,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    // table use during this search alone
+    pub tt: TtStats,
+}
+
+impl SearchResult {
+    pub fn nps(&self) -> f64 {
+        self.nodes as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+// width of the beam at `ply`, decaying geometrically from root_width down to 1 at the last ply
+fn width_at(params: &SearchParams, ply: i32) -> usize {
+    let gamma = (params.root_width as f32).powf(-1.0 / params.depth as f32);
+
+    ((params.root_width as f32) * gamma.powi(ply)).round().max(1.0) as usize
+}
+
+fn player(datum: &Datum, p: usize) -> &GameState {
+    if p == 0 { &datum.p1 } else { &datum.p2 }
+}
+
+// candidate moves for player `p`, scored from their own point of view with the opponent held still
+fn candidates(datum: &Datum, p: usize, known: usize, width: usize, params: &SearchParams, eval: &mut impl Evaluator) -> anyhow::Result<Vec<Move>> {
+    let mut score = |states: &[&GameState]| -> anyhow::Result<Vec<f32>> {
+        let leaves: Vec<Datum> = states.iter()
+            .map(|&state| {
+                let mut leaf = datum.clone();
+                if p == 0 {
+                    leaf.p1 = state.clone();
+                } else {
+                    leaf.p2 = state.clone();
+                }
+                leaf
+            })
+            .collect();
+
+        let values = eval.evaluate_batch(&leaves)?;
+
+        Ok(if p == 0 { values } else { values.into_iter().map(|v| -v).collect() })
+    };
+
+    beam(player(datum, p), known, params.beam_depth, params.beam_width, width, params.batch_size, &mut score)
+}
+
+// player `p` plays `mv`, their attack goes into the opponent's meter
+fn apply(datum: &Datum, p: usize, mv: &Move) -> Datum {
+    let filler = player(datum, p).queue[4];
+
+    // hole columns aren't known ahead of time, every batch gets the same one
+    versus::play_turn(datum, p, mv, || filler, || 0)
+}
+
+fn consumed(state: &GameState, mv: &Move) -> usize {
+    if mv.kind != state.current_piece && state.hold.is_none() { 2 } else { 1 }
+}
+
+fn terminal_value(state: State) -> Option<f32> {
+    match state {
+        State::PLAYING => None,
+        State::P1_WIN => Some(1.0),
+        State::P2_WIN => Some(-1.0),
+        State::DRAW => Some(0.0),
+    }
+}
+
+// values of positions that end the search, batching the ones that need the evaluator
+fn evaluate_leaves(leaves: &[Datum], batch_size: usize, eval: &mut impl Evaluator) -> anyhow::Result<Vec<f32>> {
+    let mut values: Vec<f32> = leaves.iter()
+        .map(|leaf| terminal_value(leaf.state).unwrap_or(0.0))
+        .collect();
+
+    let pending: Vec<usize> = (0..leaves.len())
+        .filter(|&i| terminal_value(leaves[i].state).is_none())
+        .collect();
+
+    for chunk in pending.chunks(batch_size.max(1)) {
+        let batch: Vec<Datum> = chunk.iter().map(|&i| leaves[i].clone()).collect();
+
+        for (&i, value) in chunk.iter().zip(eval.evaluate_batch(&batch)?) {
+            values[i] = value;
+        }
+    }
+
+    Ok(values)
+}
+
+// P1 maximizes, P2 minimizes. Players alternate plies, P1 moves on even plies.
+// An evaluator error ends the whole search.
+#[allow(clippy::too_many_arguments)]
+fn alphabeta(datum: &Datum, known: [usize; 2], ply: i32, params: &SearchParams, mut alpha: f32, mut beta: f32, search: &mut Search, eval: &mut impl Evaluator) -> anyhow::Result<f32> {
+    search.nodes += 1;
+
+    // the result is thrown away once the deadline passes
+    if search.out_of_time() {
+        return Ok(0.0);
+    }
+
+    if let Some(value) = terminal_value(datum.state) {
+        return Ok(value);
+    }
+
+    if ply >= params.depth {
+        return eval.evaluate(datum);
+    }
+
+    let p = (ply % 2) as usize;
+
+    // out of known pieces, nothing more to search for this player
+    if known[p] == 0 {
+        return eval.evaluate(datum);
+    }
+
+    let remaining = params.depth - ply;
+    let width = width_at(params, ply);
+    let key = tt::hash(datum, p, known);
+    let mut hash_move = None;
+
+    if let Some(entry) = search.tt.probe(key) {
+        // only a search at least as deep and as wide answers this one
+        if entry.depth >= remaining && entry.width >= width && entry.beam_depth >= params.beam_depth {
+            match entry.bound {
+                Bound::Exact => return Ok(entry.value),
+                Bound::Lower => alpha = alpha.max(entry.value),
+                Bound::Upper => beta = beta.min(entry.value),
+            }
+            if alpha >= beta {
+                return Ok(entry.value);
+            }
+        }
+        hash_move = entry.best;
+    }
+
+    let (alpha_start, beta_start) = (alpha, beta);
+
+    let mut moves = candidates(datum, p, known[p], width, params, eval)?;
+
+    // no legal placement means this player has topped out
+    if moves.is_empty() {
+        return Ok(if p == 0 { -1.0 } else { 1.0 });
+    }
+
+    // try the stored best move first
+    if let Some(i) = hash_move.and_then(|best| moves.iter().position(|mv| *mv == best)) {
+        moves[..=i].rotate_right(1);
+    }
+
+    // the children are leaves, so evaluate them together instead of one call each
+    if ply + 1 >= params.depth {
+        let children: Vec<Datum> = moves.iter().map(|mv| apply(datum, p, mv)).collect();
+        search.nodes += children.len() as u64;
+
+        let values = evaluate_leaves(&children, params.batch_size, eval)?;
+
+        let mut value = if p == 0 { f32::NEG_INFINITY } else { f32::INFINITY };
+        let mut best = None;
+
+        for (mv, score) in moves.iter().zip(values) {
+            if (p == 0 && score > value) || (p == 1 && score < value) {
+                value = score;
+                best = Some(*mv);
+            }
+        }
+
+        search.tt.store(Entry { key, depth: remaining, width, beam_depth: params.beam_depth, value, bound: Bound::Exact, best });
+
+        return Ok(value);
+    }
+
+    let mut value = if p == 0 { f32::NEG_INFINITY } else { f32::INFINITY };
+    let mut best = None;
+
+    for mv in &moves {
+        let mut next_known = known;
+        next_known[p] -= consumed(player(datum, p), mv).min(known[p]);
+
+        let child = apply(datum, p, mv);
+        let score = alphabeta(&child, next_known, ply + 1, params, alpha, beta, search, eval)?;
+
+        if p == 0 {
+            if score > value {
+                value = score;
+                best = Some(*mv);
+            }
+            alpha = alpha.max(value);
+        } else {
+            if score < value {
+                value = score;
+                best = Some(*mv);
+            }
+            beta = beta.min(value);
+        }
+
+        if alpha >= beta || search.aborted {
+            break;
+        }
+    }
+
+    if search.aborted {
+        return Ok(value);
+    }
+
+    let bound = if value <= alpha_start {
+        Bound::Upper
+    } else if value >= beta_start {
+        Bound::Lower
+    } else {
+        Bound::Exact
+    };
+
+    search.tt.store(Entry { key, depth: remaining, width, beam_depth: params.beam_depth, value, bound, best });
+
+    Ok(value)
+}
+
+// P1's root moves in `datum`, best first, or none if the deadline passed. Only the first
+// score is exact, the rest failed low against it and are ordered by their upper bounds.
+pub fn minimax_search(datum: &Datum, params: &SearchParams, search: &mut Search, eval: &mut impl Evaluator) -> anyhow::Result<Vec<Move>> {
+    let known = [1 + datum.p1.queue.len(), 1 + datum.p2.queue.len()];
+    let key = tt::hash(datum, 0, known);
+
+    let mut moves = candidates(datum, 0, known[0], width_at(params, 0), params, eval)?;
+
+    if let Some(i) = search.tt.probe(key).and_then(|entry| entry.best).and_then(|best| moves.iter().position(|mv| *mv == best)) {
+        moves[..=i].rotate_right(1);
+    }
+
+    let mut best = None;
+    let mut alpha = f32::NEG_INFINITY;
+    let mut scored: Vec<(Move, f32)> = Vec::new();
+
+    for mv in moves {
+        let mut next_known = known;
+        next_known[0] -= consumed(&datum.p1, &mv);
+
+        let child = apply(datum, 0, &mv);
+        let score = alphabeta(&child, next_known, 1, params, alpha, f32::INFINITY, search, eval)?;
+
+        if search.aborted {
+            return Ok(Vec::new());
+        }
+
+        if best.is_none() || score > alpha {
+            alpha = score;
+            best = Some(mv);
+        }
+        scored.push((mv, score));
+    }
+
+    if best.is_some() {
+        search.tt.store(Entry { key, depth: params.depth, width: params.root_width, beam_depth: params.beam_depth, value: alpha, bound: Bound::Exact, best });
+    }
+
+    // stable, so the best move stays ahead of any move that tied with it
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Ok(scored.into_iter().map(|(mv, _)| mv).collect())
+}
+
+// Iterative deepening under a wall-clock budget. Each iteration deepens the minimax by a ply
+// and the beam along with it, up to the depths in `params`. The moves from the last iteration
+// that finished are returned; if none finished, the moves of a one-ply beam are used instead.
+// An evaluator error is returned as is, there is no move to fall back on without the evaluator.
+pub fn timed_search(datum: &Datum, params: &SearchParams, budget: Duration, search: &mut Search, eval: &mut impl Evaluator) -> anyhow::Result<SearchResult> {
+    let start = Instant::now();
+
+    search.nodes = 0;
+    search.aborted = false;
+    search.deadline = Some(start + budget);
+
+    let tt_start = search.tt.stats;
+
+    let mut result = SearchResult {
+        best: None,
+        moves: Vec::new(),
+        depth: 0,
+        beam_depth: 0,
+        nodes: 0,
+        elapsed: Duration::ZERO,
+        tt: TtStats::default(),
+    };
+
+    let mut failed = None;
+
+    for depth in 1..=params.depth {
+        let iteration = SearchParams {
+            depth,
+            beam_depth: depth.min(params.beam_depth),
+            ..*params
+        };
+
+        let moves = match minimax_search(datum, &iteration, search, eval) {
+            Ok(moves) => moves,
+            Err(err) => {
+                failed = Some(err);
+                break;
+            }
+        };
+
+        if search.aborted {
+            break;
+        }
+
+        result.moves = moves;
+        result.depth = iteration.depth;
+        result.beam_depth = iteration.beam_depth;
+    }
+
+    search.deadline = None;
+    search.aborted = false;
+
+    if let Some(err) = failed {
+        return Err(err);
+    }
+
+    if result.moves.is_empty() {
+        let known = 1 + datum.p1.queue.len();
+        result.moves = candidates(datum, 0, known, params.root_width, &SearchParams { beam_depth: 1, ..*params }, eval)?;
+    }
+
+    result.best = result.moves.first().copied();
+
+    result.nodes = search.nodes;
+    result.elapsed = start.elapsed();
+    result.tt = search.tt.stats.since(&tt_start);
+
+    Ok(result)
+}
+
+// Anything that picks a placement for P1. To get P2's move, swap the players first.
+// None means no legal placement, errors are the bot's own, like a failing evaluator.
+pub trait Bot {
+    fn suggest(&mut self, datum: &Datum) -> anyhow::Result<Option<Move>>;
+
+    // every move the bot would consider playing, best first
+    fn ranked_moves(&mut self, datum: &Datum) -> anyhow::Result<Vec<Move>> {
+        Ok(self.suggest(datum)?.into_iter().collect())
+    }
+
+    // what is left of P1's bag after their last queue piece, for bots that sample unseen pieces
+    fn set_bag_mask(&mut self, _bag_mask: u8) {}
+}
+
+// the same position from P2's side
+pub fn swap_players(datum: &Datum) -> Datum {
+    let state = match datum.state {
+        State::P1_WIN => State::P2_WIN,
+        State::P2_WIN => State::P1_WIN,
+        other => other,
+    };
+
+    Datum {
+        p1: datum.p2.clone(),
+        p2: datum.p1.clone(),
+        state,
+        ..datum.clone()
+    }
+}
+
+// the minimax search above, with its evaluator and transposition table
+pub struct Hachi<E: Evaluator> {
+    pub params: SearchParams,
+    pub budget: Duration,
+    pub search: Search,
+    pub eval: E,
+}
+
+impl<E: Evaluator> Hachi<E> {
+    pub fn new(eval: E, params: SearchParams, budget: Duration) -> Self {
+        Hachi {
+            params,
+            budget,
+            search: Search::new(1 << 20),
+            eval,
+        }
+    }
+}
+
+impl<E: Evaluator> Bot for Hachi<E> {
+    fn suggest(&mut self, datum: &Datum) -> anyhow::Result<Option<Move>> {
+        Ok(timed_search(datum, &self.params, self.budget, &mut self.search, &mut self.eval)?.best)
+    }
+
+    fn ranked_moves(&mut self, datum: &Datum) -> anyhow::Result<Vec<Move>> {
+        Ok(timed_search(datum, &self.params, self.budget, &mut self.search, &mut self.eval)?.moves)
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use features::game::{Datum, GameState};
+use features::placement::{hold_piece, play};
+use features::rng::Rng;
+
+use tetris::board::Board;
+use tetris::moves::Move;
+use tetris::movegen::movegen;
+use tetris::piece::Piece;
+
+use crate::bot::Bot;
+
+/*
+    Port of bot_comboer from bots/comboer.cpp.
+
+    Only placements that clear a line are kept, so the search follows combo
+    chains until they break. Pieces past the visible queue are sampled, and
+    every root move is scored by the average chain length it reaches over
+    `speculation_split_size` sampled futures.
+
+    The C++ grows every chain breadth first. Here chains are searched depth
+    first with the positions already seen remembered, and each root placement
+    stops after chain_nodes placements, keeping the longest chain found by
+    then, so stacks that clear on almost every placement can't blow up. Roots
+    are ranked by the chain length they reach on average over the futures,
+    as comboer.cpp does.
+*/
+
+#[derive(Debug, Clone, Copy)]
+pub struct ComboerParams {
+    // placements searched from the root
+    pub beam_depth: usize,
+    pub speculation_split_size: usize,
+}
+
+impl Default for ComboerParams {
+    fn default() -> Self {
+        ComboerParams {
+            beam_depth: 10,
+            speculation_split_size: 2,
+        }
+    }
+}
+
+// every line clearing placement of the current and hold piece, with the sampled pieces drawn afterwards
+fn clears(state: &GameState, drawn: usize, future: &[Piece]) -> Vec<(Move, GameState, usize)> {
+    let mut pieces = vec![state.current_piece];
+    let held = hold_piece(state);
+    if held != state.current_piece {
+        pieces.push(held);
+    }
+
+    let mut children = Vec::new();
+
+    for piece in pieces {
+        for mv in movegen(&state.board, piece) {
+            let mut next = drawn;
+            let (child, lines) = play(state, &mv, || {
+                let piece = future[next];
+                next += 1;
+                piece
+            });
+
+            if lines > 0 {
+                children.push((mv, child, next));
+            }
+        }
+    }
+
+    children
+}
+
+// placements tried per root placement in each sampled future
+const chain_nodes: usize = 4096;
+
+struct ChainSearch {
+    // Results by board, current piece, hold, pieces drawn and depth left. The pieces drawn
+    // decide the queue, so chains that meet in the same position are only searched once.
+    // Only results the budget didn't cut short are kept.
+    seen: HashMap<(Board, Piece, Option<Piece>, usize, usize), usize>,
+    nodes: usize,
+}
+
+// longest run of clears from `state`, at most `depth` long, and whether the budget let it finish
+fn chain(state: &GameState, drawn: usize, future: &[Piece], depth: usize, search: &mut ChainSearch) -> (usize, bool) {
+    if depth == 0 {
+        return (0, true);
+    }
+
+    let key = (state.board, state.current_piece, state.hold, drawn, depth);
+    if let Some(&best) = search.seen.get(&key) {
+        return (best, true);
+    }
+
+    let mut best = 0;
+    let mut exact = true;
+
+    for (_, child, next) in clears(state, drawn, future) {
+        if search.nodes >= chain_nodes {
+            exact = false;
+            break;
+        }
+        search.nodes += 1;
+
+        let (length, child_exact) = chain(&child, next, future, depth - 1, search);
+        best = best.max(1 + length);
+        exact &= child_exact;
+
+        if best == depth {
+            // nothing longer is possible, so the result is exact whatever was cut short
+            exact = true;
+            break;
+        }
+    }
+
+    if exact {
+        search.seen.insert(key, best);
+    }
+    (best, exact)
+}
+
+// `bag_mask` describes the bag after the last queue piece, see features::rng.
+// Returns None when no placement clears a line.
+pub fn comboer(state: &GameState, bag_mask: u8, seed: u32, params: &ComboerParams) -> Option<Move> {
+    let splits = params.speculation_split_size.max(1);
+    let depth = params.beam_depth.max(1);
+
+    // (root, summed chain length, futures it was searched in)
+    let mut totals: Vec<(Move, usize, usize)> = Vec::new();
+
+    for split in 0..splits {
+        let mut rng = Rng::with_bag(seed.wrapping_add(split as u32), bag_mask);
+        // each placement draws at most two pieces
+        let future: Vec<Piece> = (0..2 * depth + 2).map(|_| rng.next_piece()).collect();
+        let mut search = ChainSearch { seen: HashMap::new(), nodes: 0 };
+
+        for (root, child, next) in clears(state, 0, &future) {
+            search.nodes = 0;
+            let length = 1 + chain(&child, next, &future, depth - 1, &mut search).0;
+
+            match totals.iter_mut().find(|(mv, _, _)| *mv == root) {
+                Some((_, sum, count)) => {
+                    *sum += length;
+                    *count += 1;
+                }
+                None => totals.push((root, length, 1)),
+            }
+        }
+    }
+
+    // highest average chain length, compared without dividing
+    totals.into_iter()
+        .reduce(|best, root| if root.1 * best.2 > best.1 * root.2 { root } else { best })
+        .map(|(mv, _, _)| mv)
+}
+
+pub struct Comboer {
+    pub params: ComboerParams,
+    // the bag after P1's last queue piece, see Bot::set_bag_mask
+    pub bag_mask: u8,
+    seed: u32,
+}
+
+impl Comboer {
+    pub fn new(params: ComboerParams, seed: u32) -> Self {
+        Comboer { params, bag_mask: 0, seed }
+    }
+}
+
+impl Bot for Comboer {
+    fn suggest(&mut self, datum: &Datum) -> anyhow::Result<Option<Move>> {
+        self.seed = self.seed.wrapping_add(self.params.speculation_split_size as u32);
+        Ok(comboer(&datum.p1, self.bag_mask, self.seed, &self.params))
+    }
+
+    fn set_bag_mask(&mut self, bag_mask: u8) {
+        self.bag_mask = bag_mask;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // rows with columns 0..4 open, each cleared by a flat I
+    fn gaps(rows: i8) -> GameState {
+        let mut state = GameState::new(Piece::I, [Piece::I; 5]);
+        for y in 0..rows {
+            for x in 4..10 {
+                state.board.set(x, y);
+            }
+        }
+        state
+    }
+
+    fn search() -> ChainSearch {
+        ChainSearch { seen: HashMap::new(), nodes: 0 }
+    }
+
+    #[test]
+    fn chain_follows_the_clears() {
+        let future = [Piece::I; 8];
+        assert_eq!(chain(&gaps(2), 0, &future, 4, &mut search()), (2, true));
+        assert_eq!(chain(&gaps(6), 0, &future, 3, &mut search()), (3, true));
+        assert_eq!(chain(&GameState::new(Piece::I, [Piece::I; 5]), 0, &future, 4, &mut search()), (0, true));
+    }
+
+    #[test]
+    fn chain_stays_within_budget() {
+        let future = [Piece::I; 18];
+        let mut search = search();
+        chain(&gaps(16), 0, &future, 8, &mut search);
+
+        assert!(search.nodes <= chain_nodes);
+    }
+
+    #[test]
+    fn cut_short_chains_are_not_kept() {
+        let future = [Piece::I; 18];
+        let mut search = search();
+        search.nodes = chain_nodes;
+
+        assert_eq!(chain(&gaps(4), 0, &future, 4, &mut search), (0, false));
+        assert!(search.seen.is_empty());
+
+        search.nodes = 0;
+        assert_eq!(chain(&gaps(4), 0, &future, 4, &mut search), (4, true));
+    }
+
+    #[test]
+    fn defaults_match_the_reference() {
+        let params = ComboerParams::default();
+        assert_eq!((params.beam_depth, params.speculation_split_size), (10, 2));
+    }
+
+    #[test]
+    fn comboer_needs_a_clear() {
+        let params = ComboerParams::default();
+        assert!(comboer(&gaps(3), 0, 1, &params).is_some());
+        assert!(comboer(&GameState::new(Piece::I, [Piece::I; 5]), 0, 1, &params).is_none());
+    }
+}
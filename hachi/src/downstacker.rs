@@ -0,0 +1,191 @@
+use features::game::{Datum, GameState};
+use features::placement::{hold_piece, play};
+use features::rng::Rng;
+
+use tetris::moves::Move;
+use tetris::movegen::movegen;
+use tetris::piece::Piece;
+
+use crate::bot::Bot;
+
+/*
+    Port of bot_downstacker from bots/downstacker.cpp.
+
+    Single player beam search that rewards line clears. Pieces past the
+    visible queue are unknown, so the search is repeated over
+    `speculation_split_size` sampled futures and every root move is credited
+    with its best leaf in each of them. The root with the highest summed
+    score wins, as in the C++, so a root only counts in the futures it
+    survived in.
+
+    One deliberate difference: the C++ adds first_empty_row_from_bottom to a
+    leaf's score, but that scan reads the rows of its packed 10x32 board in a
+    different order than they are stored, and ends up near constant for any
+    playable stack. Here the stack height is subtracted instead, which is what
+    the name and the bot are after. The defaults are the C++ benchmark's
+    (depth 10, width 1000, one split).
+*/
+
+// indexed by lines cleared, not clearing is penalised
+const line_clear_score: [i32; 5] = [-100, 200, 300, 400, 2000];
+
+#[derive(Debug, Clone, Copy)]
+pub struct DownstackerParams {
+    // placements searched from the root
+    pub beam_depth: usize,
+    pub beam_width: usize,
+    pub speculation_split_size: usize,
+}
+
+impl Default for DownstackerParams {
+    fn default() -> Self {
+        DownstackerParams {
+            beam_depth: 10,
+            beam_width: 1000,
+            speculation_split_size: 1,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Node {
+    state: GameState,
+    // pieces taken from the sampled future so far
+    drawn: usize,
+    root: Option<Move>,
+    line_clear_eval: i32,
+    eval_score: i32,
+}
+
+impl Node {
+    fn total(&self) -> i32 {
+        self.eval_score + self.line_clear_eval
+    }
+}
+
+// lowest row with nothing in it, which is the stack height, see the module comment
+fn first_empty_row(state: &GameState) -> i32 {
+    state.board.heights().iter().copied().max().unwrap_or(0) as i32
+}
+
+fn expand(node: &Node, future: &[Piece], children: &mut Vec<Node>) {
+    let mut pieces = vec![node.state.current_piece];
+    let held = hold_piece(&node.state);
+    if held != node.state.current_piece {
+        pieces.push(held);
+    }
+
+    for piece in pieces {
+        for mv in movegen(&node.state.board, piece) {
+            let mut drawn = node.drawn;
+            let (state, lines) = play(&node.state, &mv, || {
+                let piece = future[drawn];
+                drawn += 1;
+                piece
+            });
+
+            children.push(Node {
+                eval_score: -first_empty_row(&state),
+                line_clear_eval: node.line_clear_eval + line_clear_score[lines.min(4) as usize],
+                root: node.root.or(Some(mv)),
+                state,
+                drawn,
+            });
+        }
+    }
+}
+
+// keeps the `width` best nodes, in no particular order
+fn prune(nodes: &mut Vec<Node>, width: usize) {
+    if nodes.len() > width {
+        nodes.select_nth_unstable_by(width, |a, b| b.total().cmp(&a.total()));
+        nodes.truncate(width);
+    }
+}
+
+// best leaf score reached by each root move with one sampled future
+fn best_per_root(state: &GameState, future: &[Piece], params: &DownstackerParams) -> Vec<(Move, i32)> {
+    let mut nodes = vec![Node {
+        state: state.clone(),
+        drawn: 0,
+        root: None,
+        line_clear_eval: 0,
+        eval_score: 0,
+    }];
+
+    for _ in 0..params.beam_depth.max(1) {
+        prune(&mut nodes, params.beam_width);
+
+        let mut children = Vec::with_capacity(nodes.len() * 40);
+        for node in &nodes {
+            expand(node, future, &mut children);
+        }
+
+        // topped out everywhere, score what we have
+        if children.is_empty() {
+            break;
+        }
+        nodes = children;
+    }
+
+    let mut best: Vec<(Move, i32)> = Vec::new();
+
+    for node in &nodes {
+        let Some(root) = node.root else { continue };
+
+        match best.iter_mut().find(|(mv, _)| *mv == root) {
+            Some((_, score)) => *score = (*score).max(node.total()),
+            None => best.push((root, node.total())),
+        }
+    }
+
+    best
+}
+
+// `bag_mask` describes the bag after the last queue piece, see features::rng.
+// Each speculation split samples its future with `seed` plus the split index.
+pub fn downstacker(state: &GameState, bag_mask: u8, seed: u32, params: &DownstackerParams) -> Option<Move> {
+    // (root, summed best score)
+    let mut totals: Vec<(Move, i64)> = Vec::new();
+
+    for split in 0..params.speculation_split_size.max(1) {
+        let mut rng = Rng::with_bag(seed.wrapping_add(split as u32), bag_mask);
+        // each placement draws at most two pieces
+        let future: Vec<Piece> = (0..2 * params.beam_depth + 2).map(|_| rng.next_piece()).collect();
+
+        for (root, score) in best_per_root(state, &future, params) {
+            match totals.iter_mut().find(|(mv, _)| *mv == root) {
+                Some((_, sum)) => *sum += score as i64,
+                None => totals.push((root, score as i64)),
+            }
+        }
+    }
+
+    totals.into_iter()
+        .max_by_key(|&(_, sum)| sum)
+        .map(|(mv, _)| mv)
+}
+
+pub struct Downstacker {
+    pub params: DownstackerParams,
+    // the bag after P1's last queue piece, see Bot::set_bag_mask
+    pub bag_mask: u8,
+    seed: u32,
+}
+
+impl Downstacker {
+    pub fn new(params: DownstackerParams, seed: u32) -> Self {
+        Downstacker { params, bag_mask: 0, seed }
+    }
+}
+
+impl Bot for Downstacker {
+    fn suggest(&mut self, datum: &Datum) -> anyhow::Result<Option<Move>> {
+        self.seed = self.seed.wrapping_add(self.params.speculation_split_size as u32);
+        Ok(downstacker(&datum.p1, self.bag_mask, self.seed, &self.params))
+    }
+
+    fn set_bag_mask(&mut self, bag_mask: u8) {
+        self.bag_mask = bag_mask;
+    }
+}
@@ -0,0 +1,259 @@
+use std::path::Path;
+use std::time::Instant;
+
+use ndarray::{Array1, Array2};
+use ort::{
+    session::{Session, builder::GraphOptimizationLevel},
+    value::{TensorRef, ValueType},
+};
+
+use features::feature_extractor::{check_input_width, feature_vector, input_width};
+use features::game::{Datum, GameState};
+use features::static_features::{StaticFeatures, get_static_features};
+use features::xgboost::TreeModel;
+
+// Scores two-player positions. Values are P1's win value in [-1, 1]. An evaluator that
+// can't score a position returns the error, it never makes up a value.
+pub trait Evaluator {
+    fn evaluate(&mut self, datum: &Datum) -> anyhow::Result<f32>;
+
+    fn evaluate_batch(&mut self, data: &[Datum]) -> anyhow::Result<Vec<f32>> {
+        data.iter().map(|datum| self.evaluate(datum)).collect()
+    }
+}
+
+// one feature_vector() per row
+fn feature_rows(data: &[Datum]) -> Array2<f32> {
+    let flat: Vec<f32> = data.iter().flat_map(feature_vector).collect();
+    Array2::from_shape_vec((data.len(), input_width), flat).unwrap()
+}
+
+pub struct OnnxEvaluator {
+    session: Session,
+}
+
+impl OnnxEvaluator {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let session = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .commit_from_file(path)?;
+
+        let input = session.inputs.iter()
+            .find(|input| input.name == "input")
+            .ok_or_else(|| anyhow::anyhow!("model has no input named \"input\""))?;
+
+        match &input.input_type {
+            ValueType::Tensor { shape, .. } => {
+                let width = shape.last().copied().unwrap_or(-1);
+                check_input_width(usize::try_from(width).unwrap_or(0))?;
+            }
+            other => anyhow::bail!("model input is {:?}, expected a tensor", other),
+        }
+
+        Ok(OnnxEvaluator { session })
+    }
+
+    // one prediction per row of `rows`
+    pub fn predict(&mut self, rows: &Array2<f32>) -> anyhow::Result<Vec<f32>> {
+        let input = TensorRef::from_array_view((rows.shape(), rows.as_slice().unwrap()))?;
+
+        let outputs = self.session.run(ort::inputs!["input" => input])?;
+
+        let (_, values) = outputs["variable"].try_extract_tensor::<f32>()?;
+
+        Ok(values.iter().map(|v| v.clamp(-1.0, 1.0)).collect())
+    }
+}
+
+impl Evaluator for OnnxEvaluator {
+    fn evaluate(&mut self, datum: &Datum) -> anyhow::Result<f32> {
+        Ok(self.evaluate_batch(std::slice::from_ref(datum))?[0])
+    }
+
+    fn evaluate_batch(&mut self, data: &[Datum]) -> anyhow::Result<Vec<f32>> {
+        if data.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let values = self.predict(&feature_rows(data)).map_err(|err| err.context("onnx inference failed"))?;
+
+        if values.len() != data.len() {
+            anyhow::bail!("onnx model gave {} predictions for {} rows", values.len(), data.len());
+        }
+
+        Ok(values)
+    }
+}
+
+pub struct TreeEvaluator {
+    model: TreeModel,
+}
+
+impl TreeEvaluator {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let model = TreeModel::load(path)?;
+        model.inputs().map_err(anyhow::Error::msg)?;
+
+        Ok(TreeEvaluator { model })
+    }
+}
+
+impl Evaluator for TreeEvaluator {
+    fn evaluate(&mut self, datum: &Datum) -> anyhow::Result<f32> {
+        Ok(self.model.predict_selected(&feature_vector(datum)).clamp(-1.0, 1.0))
+    }
+
+    fn evaluate_batch(&mut self, data: &[Datum]) -> anyhow::Result<Vec<f32>> {
+        if data.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let rows = feature_rows(data);
+
+        Ok(self.model.predict_selected_batch(rows.as_slice().unwrap(), input_width)
+            .into_iter()
+            .map(|v| v.clamp(-1.0, 1.0))
+            .collect())
+    }
+}
+
+// Hand-tuned weights over the static features, no model file needed.
+// Each side gets a score and the difference is squashed into [-1, 1].
+pub struct HandcraftedEvaluator {
+    pub max_height: f32,
+    pub bumpiness: f32,
+    pub holes: f32,
+    pub coveredness: f32,
+    pub row_transitions: f32,
+    pub well_depth: f32,
+    pub meter: f32,
+    pub combo: f32,
+    pub b2b: f32,
+    pub scale: f32,
+}
+
+impl Default for HandcraftedEvaluator {
+    fn default() -> Self {
+        HandcraftedEvaluator {
+            max_height: -0.5,
+            bumpiness: -0.05,
+            holes: -2.0,
+            coveredness: -0.3,
+            row_transitions: -0.2,
+            well_depth: 0.4,
+            meter: -0.6,
+            combo: 0.5,
+            b2b: 0.8,
+            scale: 0.05,
+        }
+    }
+}
+
+impl HandcraftedEvaluator {
+    fn weights(&self) -> Array1<f32> {
+        Array1::from(vec![
+            self.max_height,
+            self.bumpiness,
+            self.holes,
+            self.coveredness,
+            self.row_transitions,
+            self.well_depth,
+            self.meter,
+            self.combo,
+            self.b2b,
+        ])
+    }
+
+    // the terms `weights` multiply, in the same order
+    fn terms(state: &GameState) -> [f32; 9] {
+        let sf: StaticFeatures = get_static_features(state);
+
+        [
+            sf.sunbeam_max_height as f32,
+            sf.sunbeam_bumpiness as f32,
+            sf.cc_holes as f32,
+            sf.cc_coveredness as f32,
+            sf.cc_row_transitions as f32,
+            (sf.sunbeam_well_depth as f32).min(4.0),
+            state.meter as f32,
+            state.combo as f32,
+            (state.b2b as f32).min(4.0),
+        ]
+    }
+}
+
+impl Evaluator for HandcraftedEvaluator {
+    fn evaluate(&mut self, datum: &Datum) -> anyhow::Result<f32> {
+        Ok(self.evaluate_batch(std::slice::from_ref(datum))?[0])
+    }
+
+    // P1's terms minus P2's, one row per position, all scored with a single product
+    fn evaluate_batch(&mut self, data: &[Datum]) -> anyhow::Result<Vec<f32>> {
+        let flat: Vec<f32> = data.iter()
+            .flat_map(|datum| {
+                let (p1, p2) = (Self::terms(&datum.p1), Self::terms(&datum.p2));
+                std::array::from_fn::<f32, 9, _>(|i| p1[i] - p2[i])
+            })
+            .collect();
+        let rows = Array2::from_shape_vec((data.len(), 9), flat).unwrap();
+
+        Ok(rows.dot(&self.weights())
+            .into_iter()
+            .map(|score| (score * self.scale).tanh())
+            .collect())
+    }
+}
+
+// Times `evaluator` over `data` in batches of `batch_size`, reported the same way as train/perf.py.
+// `data` should hold distinct positions, repeats would flatter anything that caches.
+// Returns predictions per second.
+pub fn bench(evaluator: &mut impl Evaluator, data: &[Datum], batch_size: usize) -> anyhow::Result<f64> {
+    let t = Instant::now();
+
+    for chunk in data.chunks(batch_size.max(1)) {
+        evaluator.evaluate_batch(chunk)?;
+    }
+
+    let elapsed = t.elapsed().as_secs_f64();
+    println!("{} predictions in {:.4}s (batch size {})", data.len(), elapsed, batch_size);
+
+    Ok(data.len() as f64 / elapsed.max(1e-9))
+}
+
+// Times feature_vector alone over `data`, which every leaf pays before any model runs.
+// The max_combo lookahead is most of it. Returns rows per second.
+pub fn bench_features(data: &[Datum]) -> f64 {
+    let t = Instant::now();
+
+    let width: usize = data.iter().map(|datum| feature_vector(datum).len()).sum();
+
+    let elapsed = t.elapsed().as_secs_f64();
+    println!("{} feature vectors ({} values) in {:.4}s", data.len(), width, elapsed);
+
+    data.len() as f64 / elapsed.max(1e-9)
+}
+
+#[cfg(test)]
+mod tests {
+    use tetris::piece::Piece;
+
+    use super::*;
+    use crate::bot::swap_players;
+    use features::game::State;
+
+    #[test]
+    fn handcrafted_prefers_the_cleaner_board() {
+        let mut p2 = GameState::new(Piece::T, [Piece::I; 5]);
+        for y in 0..6 {
+            p2.board.set(y % 10, y);
+        }
+        let datum = Datum { p1: GameState::new(Piece::T, [Piece::I; 5]), p2, state: State::PLAYING, game_id: 0, move_index: 0 };
+
+        let mut eval = HandcraftedEvaluator::default();
+        let values = eval.evaluate_batch(&[datum.clone(), swap_players(&datum)]).unwrap();
+
+        assert!(values[0] > 0.0);
+        assert_eq!(values[1], -values[0]);
+        assert_eq!(eval.evaluate(&datum).unwrap(), values[0]);
+    }
+}
@@ -0,0 +1,7 @@
+pub mod bot;
+pub mod comboer;
+pub mod downstacker;
+pub mod eval;
+pub mod selfplay;
+pub mod tbp;
+pub mod tt;
@@ -1,22 +1,102 @@
-use ndarray::Array2;
-use ort::{
-    session::{Session, builder::GraphOptimizationLevel},
-    value::TensorRef,
-};
+use std::env;
+use std::io;
+use std::time::Duration;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut session = Session::builder()?
-        .with_optimization_level(GraphOptimizationLevel::Level3)?
-        .commit_from_file("models/big_model.onnx")?;
+use hachi::bot::{Search, SearchParams, timed_search};
+use hachi::eval::{HandcraftedEvaluator, OnnxEvaluator, TreeEvaluator, bench, bench_features};
+use hachi::selfplay::{make_bot, play_game};
+use hachi::tbp;
+
+use features::game::{Datum, GameState, State};
+
+use tetris::piece::Piece;
+
+fn bench_position() -> Datum {
+    let queue = [Piece::I, Piece::J, Piece::L, Piece::O, Piece::S];
+
+    Datum {
+        p1: GameState::new(Piece::T, queue),
+        p2: GameState::new(Piece::Z, queue),
+        state: State::PLAYING,
+        game_id: 0,
+        move_index: 0,
+    }
+}
+
+// Positions from quick bot games, so every row benchmarked is a different one
+fn bench_positions(amount: usize) -> anyhow::Result<Vec<Datum>> {
+    let mut data = Vec::with_capacity(amount);
+    let mut seed = 0;
+
+    while data.len() < amount {
+        let spec = "downstacker,beam_depth=1,beam_width=4,splits=1";
+        let mut bots = [make_bot(spec, 2 * seed)?, make_bot(spec, 2 * seed + 1)?];
+
+        play_game(&mut bots, 0, seed, |datum| {
+            if datum.state == State::PLAYING && data.len() < amount {
+                data.push(datum.clone());
+            }
+            Ok(())
+        })?;
+
+        seed += 1;
+    }
+
+    Ok(data)
+}
+
+// `hachi bench search [milliseconds]` runs one timed search with the handcrafted evaluator
+fn run_search_bench(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let budget: u64 = args.get(1).map_or(Ok(300), |s| s.parse())?;
+
+    let mut search = Search::new(1 << 20);
+    let result = timed_search(&bench_position(), &SearchParams::default(), Duration::from_millis(budget), &mut search, &mut HandcraftedEvaluator::default())?;
 
-    let input_data = Array2::from_shape_vec((1, 1650), vec![0.0f32; 1650])?;
+    println!("depth {} (beam {}), {} nodes in {:.4}s", result.depth, result.beam_depth, result.nodes, result.elapsed.as_secs_f64());
+    println!("{:.0} nodes per second", result.nps());
+    println!("tt: {} probes, {:.1}% hits, {} stores, {} overwrites", result.tt.probes, 100.0 * result.tt.hit_rate(), result.tt.stores, result.tt.overwrites);
 
-    let input_ref = TensorRef::from_array_view((input_data.shape(), input_data.as_slice().unwrap()))?;
+    Ok(())
+}
+
+// `hachi bench <onnx|tree|handcrafted|features> [predictions] [batch size]`
+fn run_bench(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if args.first().map(String::as_str) == Some("search") {
+        return run_search_bench(args);
+    }
+
+    let amount: usize = args.get(1).map_or(Ok(100000), |s| s.parse())?;
+    let batch_size: usize = args.get(2).map_or(Ok(256), |s| s.parse())?;
+
+    let data = bench_positions(amount)?;
+
+    let per_second = match args.first().map(String::as_str) {
+        Some("onnx") => bench(&mut OnnxEvaluator::load("models/big_model.onnx")?, &data, batch_size)?,
+        Some("tree") => bench(&mut TreeEvaluator::load("models/model.ubj")?, &data, batch_size)?,
+        Some("features") => bench_features(&data),
+        _ => bench(&mut HandcraftedEvaluator::default(), &data, batch_size)?,
+    };
+
+    println!("{:.0} per second", per_second);
 
-    let outputs = session.run(ort::inputs!["input" => input_ref])?;
+    Ok(())
+}
+
+// `hachi [bot]` speaks TBP on stdin/stdout, bots are given as in hachi::selfplay
+fn run_tbp(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut bot = make_bot(args.first().map_or("tree", String::as_str), 0)?;
 
-    let output_tensor = outputs["variable"].try_extract_tensor::<f32>()?;
-    println!("Prediction: {:?}", output_tensor);
+    tbp::run(bot.as_mut(), io::stdin().lock(), io::stdout().lock())?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().collect();
+
+    if args.get(1).map(String::as_str) == Some("bench") {
+        return run_bench(&args[2..]);
+    }
+
+    run_tbp(&args[1..])
+}
@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use features::game::{Datum, GameState, State};
+use features::rng::Rng;
+use features::versus;
+
+use crate::bot::{Bot, Hachi, SearchParams, swap_players};
+use crate::comboer::{Comboer, ComboerParams};
+use crate::downstacker::{Downstacker, DownstackerParams};
+use crate::eval::{HandcraftedEvaluator, OnnxEvaluator, TreeEvaluator};
+
+/*
+    Bot vs bot games on the versus engine, shared by the selfplay and arena binaries.
+
+    Bots are given as `<name>[,key=value...]`, for example
+    `tree,depth=4,beam_width=80,ms=50` or `downstacker,splits=2`.
+
+    tree, onnx, handcrafted: the minimax search with that evaluator.
+        depth, root_width, beam_depth, beam_width, batch_size, ms (time per move), model (path)
+    downstacker: beam_depth, beam_width, splits
+    comboer: beam_depth, splits
+*/
+
+// games that go on this long are called a draw
+pub const max_moves: u16 = 1000;
+
+fn parse_options(spec: &str) -> anyhow::Result<(&str, Vec<(&str, &str)>)> {
+    let mut parts = spec.split(',');
+    let name = parts.next().unwrap_or_default();
+
+    let options = parts
+        .map(|part| part.split_once('=').ok_or_else(|| anyhow::anyhow!("expected key=value, got {:?} in {:?}", part, spec)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok((name, options))
+}
+
+fn search_bot(name: &str, options: &[(&str, &str)]) -> anyhow::Result<Box<dyn Bot>> {
+    let mut params = SearchParams::default();
+    let mut budget = Duration::from_millis(100);
+    let mut model = None;
+
+    for &(key, value) in options {
+        match key {
+            "depth" => params.depth = value.parse()?,
+            "root_width" => params.root_width = value.parse()?,
+            "beam_depth" => params.beam_depth = value.parse()?,
+            "beam_width" => params.beam_width = value.parse()?,
+            "batch_size" => params.batch_size = value.parse()?,
+            "ms" => budget = Duration::from_millis(value.parse()?),
+            "model" => model = Some(value.to_string()),
+            other => anyhow::bail!("unknown option {:?} for {}", other, name),
+        }
+    }
+
+    Ok(match name {
+        "onnx" => Box::new(Hachi::new(OnnxEvaluator::load(model.as_deref().unwrap_or("models/big_model.onnx"))?, params, budget)),
+        "handcrafted" => Box::new(Hachi::new(HandcraftedEvaluator::default(), params, budget)),
+        _ => Box::new(Hachi::new(TreeEvaluator::load(model.as_deref().unwrap_or("models/model.ubj"))?, params, budget)),
+    })
+}
+
+pub fn make_bot(spec: &str, seed: u32) -> anyhow::Result<Box<dyn Bot>> {
+    let (name, options) = parse_options(spec)?;
+
+    match name {
+        "hachi" | "tree" | "onnx" | "handcrafted" => search_bot(name, &options),
+        "downstacker" => {
+            let mut params = DownstackerParams::default();
+            for (key, value) in options {
+                match key {
+                    "beam_depth" => params.beam_depth = value.parse()?,
+                    "beam_width" => params.beam_width = value.parse()?,
+                    "splits" => params.speculation_split_size = value.parse()?,
+                    other => anyhow::bail!("unknown option {:?} for downstacker", other),
+                }
+            }
+            Ok(Box::new(Downstacker::new(params, seed)))
+        }
+        "comboer" => {
+            let mut params = ComboerParams::default();
+            for (key, value) in options {
+                match key {
+                    "beam_depth" => params.beam_depth = value.parse()?,
+                    "splits" => params.speculation_split_size = value.parse()?,
+                    other => anyhow::bail!("unknown option {:?} for comboer", other),
+                }
+            }
+            Ok(Box::new(Comboer::new(params, seed)))
+        }
+        other => anyhow::bail!("unknown bot {:?}", other),
+    }
+}
+
+fn new_state(rng: &mut Rng) -> GameState {
+    let current = rng.next_piece();
+    let queue = [(); 5].map(|_| rng.next_piece());
+    GameState::new(current, queue)
+}
+
+// Plays one game and calls `on_position` with every position, starting from the empty
+// boards at move_index 0. Both players see the same pieces. Returns the final state.
+pub fn play_game(
+    bots: &mut [Box<dyn Bot>; 2],
+    game_id: u16,
+    seed: u32,
+    mut on_position: impl FnMut(&Datum) -> anyhow::Result<()>,
+) -> anyhow::Result<State> {
+    let mut pieces = [Rng::new(seed), Rng::new(seed)];
+    let mut holes = Rng::new(seed.rotate_left(16) ^ 0x5bd1e995);
+
+    let mut datum = Datum {
+        p1: new_state(&mut pieces[0]),
+        p2: new_state(&mut pieces[1]),
+        state: State::PLAYING,
+        game_id,
+        move_index: 0,
+    };
+
+    on_position(&datum)?;
+
+    while datum.state == State::PLAYING {
+        bots[0].set_bag_mask(pieces[0].bag_mask());
+        bots[1].set_bag_mask(pieces[1].bag_mask());
+
+        let p1_move = bots[0].suggest(&datum)?;
+        let p2_move = bots[1].suggest(&swap_players(&datum))?;
+
+        datum = match (p1_move, p2_move) {
+            (Some(p1_move), Some(p2_move)) => {
+                let [p1_pieces, p2_pieces] = &mut pieces;
+                versus::step(&datum, &p1_move, &p2_move, || p1_pieces.next_piece(), || p2_pieces.next_piece(), || holes.garbage_hole())
+            }
+            _ => {
+                let mut stuck = versus::forfeit(&datum, p1_move.is_none(), p2_move.is_none());
+                stuck.move_index += 1;
+                stuck
+            }
+        };
+
+        if datum.state == State::PLAYING && datum.move_index >= max_moves {
+            datum.state = State::DRAW;
+        }
+
+        on_position(&datum)?;
+    }
+
+    Ok(datum.state)
+}
@@ -0,0 +1,435 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+
+use features::game::{Datum, GameState, State};
+use features::placement::{self, clear_lines, is_tspin, place};
+
+use tetris::board::Board;
+use tetris::movegen::movegen;
+use tetris::moves::Move;
+use tetris::piece::{Piece, Rotation};
+
+use crate::bot::Bot;
+
+/*
+    Tetris Bot Protocol frontend, JSON lines over stdin/stdout.
+    https://github.com/tetris-bot-protocol/tbp-spec
+
+    Suggestions come from the bot's own search, hachi's being the minimax
+    one. TBP is single player, so the bot plays against an opponent with an
+    empty board, the same queue and nothing incoming.
+*/
+
+// moves returned per suggestion, best first
+const suggestions: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum TbpPiece { I, O, T, L, J, S, Z }
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Orientation { North, East, South, West }
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Spin { None, Mini, Full }
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Location {
+    #[serde(rename = "type")]
+    kind: TbpPiece,
+    orientation: Orientation,
+    x: i8,
+    y: i8,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct TbpMove {
+    location: Location,
+    spin: Spin,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FrontendMessage {
+    Rules,
+    Start {
+        hold: Option<TbpPiece>,
+        queue: Vec<TbpPiece>,
+        combo: u32,
+        back_to_back: bool,
+        // rows bottom to top, null for empty cells
+        board: Vec<Vec<Option<String>>>,
+    },
+    Stop,
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        mv: TbpMove,
+    },
+    NewPiece {
+        piece: TbpPiece,
+    },
+    Quit,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BotMessage {
+    Info {
+        name: &'static str,
+        version: &'static str,
+        author: &'static str,
+        features: Vec<&'static str>,
+    },
+    Ready,
+    Suggestion {
+        moves: Vec<TbpMove>,
+    },
+}
+
+fn to_piece(piece: TbpPiece) -> Piece {
+    match piece {
+        TbpPiece::I => Piece::I,
+        TbpPiece::O => Piece::O,
+        TbpPiece::T => Piece::T,
+        TbpPiece::L => Piece::L,
+        TbpPiece::J => Piece::J,
+        TbpPiece::S => Piece::S,
+        TbpPiece::Z => Piece::Z,
+    }
+}
+
+fn from_piece(piece: Piece) -> TbpPiece {
+    match piece {
+        Piece::I => TbpPiece::I,
+        Piece::O => TbpPiece::O,
+        Piece::T => TbpPiece::T,
+        Piece::L => TbpPiece::L,
+        Piece::J => TbpPiece::J,
+        Piece::S => TbpPiece::S,
+        Piece::Z => TbpPiece::Z,
+    }
+}
+
+// The frontend says whether the move spun, movegen knows how it got there. A spin is
+// taken from movegen's placement at the same spot, a move it can't reach gets none.
+fn to_move(board: &Board, mv: &TbpMove) -> Move {
+    let r = match mv.location.orientation {
+        Orientation::North => Rotation::North,
+        Orientation::East => Rotation::East,
+        Orientation::South => Rotation::South,
+        Orientation::West => Rotation::West,
+    };
+    let kind = to_piece(mv.location.kind);
+    let (x, y) = (mv.location.x, mv.location.y);
+
+    let tspin = match mv.spin {
+        Spin::None => None,
+        Spin::Mini | Spin::Full => movegen(board, kind)
+            .into_iter()
+            .find(|m| m.x == x && m.y == y && m.r == r)
+            .and_then(|m| m.tspin),
+    };
+
+    Move { x, y, r, kind, tspin }
+}
+
+fn from_move(board: &Board, mv: &Move) -> TbpMove {
+    let orientation = match mv.r {
+        Rotation::North => Orientation::North,
+        Rotation::East => Orientation::East,
+        Rotation::South => Orientation::South,
+        Rotation::West => Orientation::West,
+    };
+
+    TbpMove {
+        location: Location { kind: from_piece(mv.kind), orientation, x: mv.x, y: mv.y },
+        spin: match placement::spin(board, mv) {
+            placement::Spin::Full => Spin::Full,
+            placement::Spin::Mini => Spin::Mini,
+            placement::Spin::None => Spin::None,
+        },
+    }
+}
+
+fn to_board(rows: &[Vec<Option<String>>]) -> Board {
+    let mut board = Board::new();
+
+    for (y, row) in rows.iter().enumerate().take(64) {
+        for (x, cell) in row.iter().enumerate().take(10) {
+            if cell.is_some() {
+                board.set(x as i8, y as i8);
+            }
+        }
+    }
+
+    board
+}
+
+// what the frontend has told us about the game so far
+struct Game {
+    board: Board,
+    // current piece first
+    queue: VecDeque<Piece>,
+    hold: Option<Piece>,
+    combo: u8,
+    b2b: u8,
+}
+
+impl Game {
+    // The position as a GameState. Short queues are padded with their last piece,
+    // None if not even the current piece is known.
+    fn state(&self) -> Option<GameState> {
+        let current = *self.queue.front()?;
+        let last = *self.queue.back()?;
+        let queue = [1, 2, 3, 4, 5].map(|i| self.queue.get(i).copied().unwrap_or(last));
+
+        let mut state = GameState::new(current, queue);
+        state.board = self.board;
+        state.hold = self.hold;
+        state.combo = self.combo;
+        state.b2b = self.b2b;
+
+        Some(state)
+    }
+
+    fn play(&mut self, mv: &Move) {
+        let Some(current) = self.queue.pop_front() else {
+            return;
+        };
+
+        if mv.kind != current {
+            if self.hold.is_none() {
+                self.queue.pop_front();
+            }
+            self.hold = Some(current);
+        }
+
+        // `mv.tspin` came from movegen in to_move
+        let tspin = is_tspin(&self.board, mv);
+        place(&mut self.board, mv);
+        let lines = clear_lines(&mut self.board);
+
+        if lines > 0 {
+            self.combo = self.combo.saturating_add(1);
+            self.b2b = if lines == 4 || tspin { self.b2b.saturating_add(1) } else { 0 };
+        } else {
+            self.combo = 0;
+        }
+    }
+}
+
+fn suggest(game: &Game, bot: &mut dyn Bot) -> anyhow::Result<Vec<TbpMove>> {
+    let Some(state) = game.state() else {
+        return Ok(Vec::new());
+    };
+
+    let datum = Datum {
+        p1: state.clone(),
+        p2: GameState::new(state.current_piece, state.queue),
+        state: State::PLAYING,
+        game_id: 0,
+        move_index: 0,
+    };
+
+    Ok(bot.ranked_moves(&datum)?
+        .iter()
+        .take(suggestions)
+        .map(|mv| from_move(&state.board, mv))
+        .collect())
+}
+
+fn send(output: &mut impl Write, message: &BotMessage) -> anyhow::Result<()> {
+    writeln!(output, "{}", serde_json::to_string(message)?)?;
+    output.flush()?;
+    Ok(())
+}
+
+// Runs until `quit` or the end of input. Lines that don't parse are reported on stderr and skipped,
+// errors from the bot end the session.
+pub fn run(bot: &mut dyn Bot, input: impl BufRead, mut output: impl Write) -> anyhow::Result<()> {
+    send(&mut output, &BotMessage::Info {
+        name: "hachi",
+        version: env!("CARGO_PKG_VERSION"),
+        author: "shakkar23",
+        features: Vec::new(),
+    })?;
+
+    let mut game: Option<Game> = None;
+
+    for line in input.lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let message: FrontendMessage = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(err) => {
+                eprintln!("ignoring tbp message {:?}: {}", line, err);
+                continue;
+            }
+        };
+
+        match message {
+            FrontendMessage::Rules => send(&mut output, &BotMessage::Ready)?,
+            FrontendMessage::Start { hold, queue, combo, back_to_back, board } => {
+                game = Some(Game {
+                    board: to_board(&board),
+                    queue: queue.into_iter().map(to_piece).collect(),
+                    hold: hold.map(to_piece),
+                    combo: combo.min(u8::MAX as u32) as u8,
+                    b2b: back_to_back as u8,
+                });
+            }
+            FrontendMessage::Stop => game = None,
+            FrontendMessage::Suggest => {
+                let moves = match game.as_ref() {
+                    Some(game) => suggest(game, bot)?,
+                    None => Vec::new(),
+                };
+                send(&mut output, &BotMessage::Suggestion { moves })?;
+            }
+            FrontendMessage::Play { mv } => {
+                if let Some(game) = game.as_mut() {
+                    let mv = to_move(&game.board, &mv);
+                    game.play(&mv);
+                }
+            }
+            FrontendMessage::NewPiece { piece } => {
+                if let Some(game) = game.as_mut() {
+                    game.queue.push_back(to_piece(piece));
+                }
+            }
+            FrontendMessage::Quit => break,
+            FrontendMessage::Unknown => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::bot::{Hachi, SearchParams};
+    use crate::eval::HandcraftedEvaluator;
+
+    fn parse(line: &str) -> FrontendMessage {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn start_message() {
+        let line = r#"{"type":"start","hold":null,"queue":["T","I","O"],"combo":2,"back_to_back":true,
+            "board":[["G",null,null,null,null,null,null,null,null,"G"],[null,null,null,null,null,null,null,null,null,"S"]]}"#;
+
+        let FrontendMessage::Start { hold, queue, combo, back_to_back, board } = parse(line) else {
+            panic!("not a start message");
+        };
+
+        assert!(hold.is_none());
+        assert_eq!(queue, vec![TbpPiece::T, TbpPiece::I, TbpPiece::O]);
+        assert_eq!((combo, back_to_back), (2, true));
+
+        let board = to_board(&board);
+        assert_eq!(board.cols[0], 0b1);
+        assert_eq!(board.cols[9], 0b11);
+        assert!(board.cols[1..9].iter().all(|&col| col == 0));
+    }
+
+    #[test]
+    fn play_message() {
+        let line = r#"{"type":"play","move":{"location":{"type":"I","orientation":"east","x":0,"y":1},"spin":"none"}}"#;
+
+        let FrontendMessage::Play { mv } = parse(line) else {
+            panic!("not a play message");
+        };
+
+        let mv = to_move(&Board::new(), &mv);
+        assert_eq!((mv.kind, mv.r, mv.x, mv.y), (Piece::I, Rotation::East, 0, 1));
+        assert!(mv.tspin.is_none());
+    }
+
+    #[test]
+    fn unknown_messages_are_tolerated() {
+        assert!(matches!(parse(r#"{"type":"hello","extra":1}"#), FrontendMessage::Unknown));
+        assert!(serde_json::from_str::<FrontendMessage>("not json").is_err());
+    }
+
+    #[test]
+    fn hold_and_clear_update_the_game() {
+        let mut board = Board::new();
+        for x in 4..10 {
+            board.set(x, 0);
+        }
+
+        let mut game = Game {
+            board,
+            queue: VecDeque::from([Piece::T, Piece::I, Piece::O]),
+            hold: None,
+            combo: 0,
+            b2b: 0,
+        };
+
+        // an I out of hold fills the row
+        game.play(&Move { x: 1, y: 0, r: Rotation::North, kind: Piece::I, tspin: None });
+
+        assert_eq!(game.hold, Some(Piece::T));
+        assert_eq!(game.queue, VecDeque::from([Piece::O]));
+        assert_eq!(game.board, Board::new());
+        assert_eq!(game.combo, 1);
+
+        let state = game.state().unwrap();
+        assert_eq!(state.current_piece, Piece::O);
+        assert_eq!(state.queue, [Piece::O; 5]);
+    }
+
+    #[test]
+    fn handshake() {
+        let input = "{\"type\":\"rules\"}\nnonsense\n{\"type\":\"suggest\"}\n{\"type\":\"quit\"}\n{\"type\":\"rules\"}\n";
+        let mut output = Vec::new();
+
+        let mut bot = Hachi::new(HandcraftedEvaluator::default(), SearchParams::default(), Duration::from_millis(10));
+        run(&mut bot, input.as_bytes(), &mut output).unwrap();
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["type"], "info");
+        assert_eq!(lines[1]["type"], "ready");
+        assert_eq!(lines[2], serde_json::json!({ "type": "suggestion", "moves": [] }));
+    }
+
+    #[test]
+    fn suggestions_come_from_the_search() {
+        let game = Game {
+            board: Board::new(),
+            queue: VecDeque::from([Piece::T, Piece::I, Piece::O, Piece::S, Piece::Z, Piece::L]),
+            hold: None,
+            combo: 0,
+            b2b: 0,
+        };
+        let params = SearchParams { depth: 2, root_width: 8, beam_depth: 1, beam_width: 8, batch_size: 64 };
+        let mut bot = Hachi::new(HandcraftedEvaluator::default(), params, Duration::from_secs(5));
+
+        let moves = suggest(&game, &mut bot).unwrap();
+        assert!(!moves.is_empty() && moves.len() <= suggestions);
+
+        let state = game.state().unwrap();
+        let datum = Datum { p1: state.clone(), p2: GameState::new(state.current_piece, state.queue), state: State::PLAYING, game_id: 0, move_index: 0 };
+        let best = bot.suggest(&datum).unwrap().unwrap();
+        assert_eq!(serde_json::to_value(moves[0]).unwrap(), serde_json::to_value(from_move(&state.board, &best)).unwrap());
+    }
+}
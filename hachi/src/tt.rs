@@ -0,0 +1,282 @@
+use features::game::{Datum, GameState};
+
+use tetris::moves::Move;
+
+/*
+    Zobrist keys for a two-player position.
+
+    Every player gets their own set of keys for board cells, current piece,
+    hold, queue slots, meter, combo and b2b, and for how many of their pieces
+    are still known. Past the visible queue the search plays filler pieces, so
+    the same boards with fewer real pieces left are a different position. The
+    keys are generated at compile time from a fixed seed, so hashes are stable
+    across runs.
+*/
+
+const cell_keys: usize = 10 * 64;
+const current_keys: usize = 7;
+const hold_keys: usize = 8; // 7 pieces + empty
+const queue_keys: usize = 5 * 7;
+const counter_keys: usize = 256;
+const known_keys: usize = 7; // current + 5 queue, down to 0
+
+const cell_offset: usize = 0;
+const current_offset: usize = cell_offset + cell_keys;
+const hold_offset: usize = current_offset + current_keys;
+const queue_offset: usize = hold_offset + hold_keys;
+const meter_offset: usize = queue_offset + queue_keys;
+const combo_offset: usize = meter_offset + counter_keys;
+const b2b_offset: usize = combo_offset + counter_keys;
+const known_offset: usize = b2b_offset + counter_keys;
+const player_keys: usize = known_offset + known_keys;
+
+const side_key: usize = 2 * player_keys;
+const key_count: usize = side_key + 1;
+
+const fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+const fn make_keys() -> [u64; key_count] {
+    let mut keys = [0u64; key_count];
+    let mut state = 0x6861636869u64; // "hachi"
+    let mut i = 0;
+    while i < key_count {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        keys[i] = splitmix64(state);
+        i += 1;
+    }
+    keys
+}
+
+static zobrist_keys: [u64; key_count] = make_keys();
+
+fn hash_player(state: &GameState, known: usize, p: usize) -> u64 {
+    let base = p * player_keys;
+    let mut h = 0u64;
+
+    for (x, &col) in state.board.cols.iter().enumerate() {
+        let mut bits = col;
+        while bits != 0 {
+            let y = bits.trailing_zeros() as usize;
+            h ^= zobrist_keys[base + cell_offset + x * 64 + y];
+            bits &= bits - 1;
+        }
+    }
+
+    h ^= zobrist_keys[base + current_offset + state.current_piece as usize];
+    h ^= zobrist_keys[base + hold_offset + state.hold.map_or(7, |piece| piece as usize)];
+
+    for (i, &piece) in state.queue.iter().enumerate() {
+        h ^= zobrist_keys[base + queue_offset + i * 7 + piece as usize];
+    }
+
+    h ^= zobrist_keys[base + meter_offset + state.meter as usize];
+    h ^= zobrist_keys[base + combo_offset + state.combo as usize];
+    h ^= zobrist_keys[base + b2b_offset + state.b2b as usize];
+    h ^= zobrist_keys[base + known_offset + known.min(known_keys - 1)];
+
+    h
+}
+
+// `p` is the player to move, `known` the real pieces each player has left
+pub fn hash(datum: &Datum, p: usize, known: [usize; 2]) -> u64 {
+    let mut h = hash_player(&datum.p1, known[0], 0) ^ hash_player(&datum.p2, known[1], 1);
+
+    if p == 1 {
+        h ^= zobrist_keys[side_key];
+    }
+
+    h
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    Lower, // value is at least this
+    Upper, // value is at most this
+}
+
+// Iterative deepening changes the widths every ply is searched with, so an entry also records
+// the width and beam depth its node was searched with. Widths deeper in the tree shrink and
+// grow together with the node's own, so comparing these two is enough.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub key: u64,
+    pub depth: i32,
+    pub width: usize,
+    pub beam_depth: i32,
+    pub value: f32,
+    pub bound: Bound,
+    pub best: Option<Move>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TtStats {
+    pub probes: u64,
+    pub hits: u64,
+    pub stores: u64,
+    pub overwrites: u64,
+}
+
+impl TtStats {
+    // what was counted after `start` was taken
+    pub fn since(&self, start: &TtStats) -> TtStats {
+        TtStats {
+            probes: self.probes - start.probes,
+            hits: self.hits - start.hits,
+            stores: self.stores - start.stores,
+            overwrites: self.overwrites - start.overwrites,
+        }
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        if self.probes == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / self.probes as f64
+    }
+}
+
+// Fixed-size, always-indexed table. A slot is replaced when it is empty, holds the
+// same position, or holds a shallower search than the one being stored.
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+    mask: usize,
+    pub stats: TtStats,
+}
+
+impl TranspositionTable {
+    // `size` is rounded up to a power of two
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1).next_power_of_two();
+
+        TranspositionTable {
+            entries: vec![None; size],
+            mask: size - 1,
+            stats: TtStats::default(),
+        }
+    }
+
+    pub fn probe(&mut self, key: u64) -> Option<Entry> {
+        self.stats.probes += 1;
+
+        match self.entries[key as usize & self.mask] {
+            Some(entry) if entry.key == key => {
+                self.stats.hits += 1;
+                Some(entry)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, entry: Entry) {
+        let slot = &mut self.entries[entry.key as usize & self.mask];
+
+        if let Some(old) = slot {
+            if old.key != entry.key && old.depth > entry.depth {
+                return;
+            }
+            if old.key != entry.key {
+                self.stats.overwrites += 1;
+            }
+        }
+
+        *slot = Some(entry);
+        self.stats.stores += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+        self.stats = TtStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use features::game::State;
+    use tetris::piece::Piece;
+
+    use super::*;
+
+    fn datum() -> Datum {
+        Datum {
+            p1: GameState::new(Piece::T, [Piece::I, Piece::O, Piece::S, Piece::Z, Piece::L]),
+            p2: GameState::new(Piece::J, [Piece::T; 5]),
+            state: State::PLAYING,
+            game_id: 0,
+            move_index: 0,
+        }
+    }
+
+    #[test]
+    fn hash_sees_every_part_of_the_position() {
+        let base = datum();
+        let h = hash(&base, 0, [6, 6]);
+
+        assert_eq!(hash(&datum(), 0, [6, 6]), h);
+        assert_ne!(hash(&base, 1, [6, 6]), h);
+        assert_ne!(hash(&base, 0, [5, 6]), h);
+        assert_ne!(hash(&base, 0, [6, 2]), h);
+
+        let changes: [fn(&mut Datum); 7] = [
+            |d| d.p1.board.set(4, 0),
+            |d| d.p2.board.set(4, 0),
+            |d| d.p1.current_piece = Piece::I,
+            |d| d.p1.hold = Some(Piece::O),
+            |d| d.p1.queue.swap(0, 1),
+            |d| d.p2.meter = 4,
+            |d| d.p1.combo = 2,
+        ];
+
+        for change in changes {
+            let mut d = datum();
+            change(&mut d);
+            assert_ne!(hash(&d, 0, [6, 6]), h);
+        }
+    }
+
+    #[test]
+    fn players_have_their_own_keys() {
+        let mut swapped = datum();
+        std::mem::swap(&mut swapped.p1, &mut swapped.p2);
+
+        assert_ne!(hash(&swapped, 0, [6, 6]), hash(&datum(), 0, [6, 6]));
+    }
+
+    fn entry(key: u64, depth: i32, value: f32, bound: Bound) -> Entry {
+        Entry { key, depth, width: 1, beam_depth: 1, value, bound, best: None }
+    }
+
+    #[test]
+    fn deeper_entries_survive_collisions() {
+        let mut tt = TranspositionTable::new(1);
+
+        tt.store(entry(1, 4, 0.5, Bound::Exact));
+        tt.store(entry(2, 2, -0.5, Bound::Lower));
+        assert!(tt.probe(2).is_none());
+        assert_eq!(tt.probe(1).map(|e| e.value), Some(0.5));
+
+        // the same position is always replaced, a deeper search of another one too
+        tt.store(entry(1, 1, 0.25, Bound::Upper));
+        assert_eq!(tt.probe(1).map(|e| (e.depth, e.bound)), Some((1, Bound::Upper)));
+
+        tt.store(entry(3, 6, 0.0, Bound::Exact));
+        assert!(tt.probe(1).is_none());
+        assert_eq!(tt.stats.overwrites, 1);
+        assert_eq!(tt.stats.hits, 2);
+
+        let start = tt.stats;
+        tt.probe(3);
+        tt.probe(4);
+        let stats = tt.stats.since(&start);
+        assert_eq!((stats.probes, stats.hits, stats.stores), (2, 1, 0));
+        assert_eq!(stats.hit_rate(), 0.5);
+
+        tt.clear();
+        assert!(tt.probe(3).is_none());
+    }
+}
@@ -1,32 +1,59 @@
 import duckdb
+import json
+import os
 import pandas as pd
 from sklearn.model_selection import train_test_split
 import time
 
 DATABASE_PATH = "./training.duckdb"
+MANIFEST_PATH = os.path.splitext(DATABASE_PATH)[0] + ".manifest.json"
 
 conn = duckdb.connect(DATABASE_PATH)
 
-sql_query = "SELECT * FROM training_data WHERE ABS(ground_truth) > 0"
+# ground_truth is NULL on rows the --target scheme doesn't reach (window, ttd) and 0 for drawn games
+sql_query = "SELECT * FROM training_data WHERE ground_truth IS NOT NULL AND ABS(ground_truth) > 0"
 
 t = time.perf_counter()
 
 df = conn.execute(sql_query).fetchdf()
 
+unlabelled = conn.execute("SELECT COUNT(*) FROM training_data WHERE ground_truth IS NULL").fetchone()[0]
+if unlabelled:
+    print(f"Skipping {unlabelled} rows without a target, run `features relabel` to label them with TD(λ)")
+
 print(f"Loaded training data in {time.perf_counter() - t:.4f}s")
 
 conn.close()
 
 state = df['state']
 
+# True for the left-right mirror images written by `--mirror`
+mirrored = df['mirrored'] if 'mirrored' in df.columns else None
+
+# TD(λ) targets written by `features relabel`, if it has been run
+td_target = df['td_target'] if 'td_target' in df.columns else None
+
+# what remains must match features::feature_extractor::feature_vector, p1 columns then p2
 df = df.drop(columns=[
     "game_id",
     "state",
     "move_index",
-])
+    "mirrored",
+    "td_target",
+], errors='ignore')
 
 print(df)
 
+# the feature columns must be exactly what the extractor wrote in its manifest
+if os.path.exists(MANIFEST_PATH):
+    with open(MANIFEST_PATH) as f:
+        manifest = json.load(f)
+    columns = [c for c in df.columns if c != 'ground_truth']
+    if columns != manifest["columns"]:
+        raise ValueError(f"training_data columns do not match {MANIFEST_PATH} (feature set v{manifest['feature_set_version']})")
+else:
+    print(f"No manifest at {MANIFEST_PATH}, skipping feature schema check")
+
 y = df['ground_truth']
 
 df = df.drop('ground_truth', axis=1)
@@ -0,0 +1,67 @@
+"""
+Trains the small models that features/src/xgboost.rs is tested against and
+writes them, with xgboost's own predictions, into features/fixtures/xgboost/.
+
+Both models are trained and saved by xgboost, as .json and as .ubj, on data
+with missing values so that default_left gets used. The regression model
+keeps a base_score of 0.5, which xgboost 3.x writes in its bracketed "[5E-1]"
+form, and the logistic one covers the sigmoid link.
+
+The expected predictions are xgboost's inplace_predict with NaN as missing,
+and predictions.json records the xgboost version that made them. The Rust
+tests refuse predictions from anywhere else.
+
+    pip install xgboost numpy
+    python train/make_xgb_fixture.py
+"""
+
+import json
+from pathlib import Path
+
+import numpy as np
+import xgboost as xgb
+
+out = Path(__file__).resolve().parent.parent / "features" / "fixtures" / "xgboost"
+
+rng = np.random.default_rng(23)
+
+X = rng.normal(size=(400, 4)).astype(np.float32)
+y_regression = X[:, 0] - 0.5 * X[:, 1] + np.where(X[:, 2] > 0, 0.75, -0.25)
+y_logistic = (X[:, 0] + X[:, 3] > 0).astype(np.float32)
+
+# knock out values after the targets are made, so missing means something different per column
+X[rng.random(X.shape) < 0.15] = np.nan
+
+models = {
+    "regression": ({"objective": "reg:squarederror", "max_depth": 3, "eta": 0.3, "base_score": 0.5}, y_regression),
+    "logistic": ({"objective": "binary:logistic", "max_depth": 3, "eta": 0.3}, y_logistic),
+}
+
+rows = np.concatenate([X[:30], np.full((1, 4), np.nan, dtype=np.float32)])
+
+out.mkdir(parents=True, exist_ok=True)
+
+source = "xgboost " + xgb.__version__
+expected = {"rows": [[None if np.isnan(x) else float(x) for x in row] for row in rows]}
+
+for name, (params, y) in models.items():
+    booster = xgb.train(params, xgb.DMatrix(X, label=y, missing=np.nan), num_boost_round=8)
+
+    predictions = None
+    for ext in ["json", "ubj"]:
+        path = out / f"{name}.{ext}"
+        booster.save_model(path)
+
+        # predict from what was saved, so both files are known to hold the same model
+        saved = xgb.Booster()
+        saved.load_model(path)
+        p = saved.inplace_predict(rows, missing=np.nan)
+
+        assert predictions is None or np.array_equal(p, predictions), f"{path} predicts differently"
+        predictions = p
+
+    expected[name] = [float(p) for p in predictions]
+    expected[f"{name}_source"] = source
+
+(out / "predictions.json").write_text(json.dumps(expected, indent=1) + "\n")
+print(f"wrote {out} with {source}")
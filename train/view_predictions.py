@@ -21,8 +21,10 @@ def get_feature_data():
         "game_id",
         "state",
         "move_index",
-        "ground_truth"
-    ])
+        "mirrored",
+        "ground_truth",
+        "td_target",
+    ], errors='ignore')
 
 
     conn.close()